@@ -0,0 +1,150 @@
+use crate::json_parser::{Command, CommandType, GroupDispatch, KeyMap, KeyMapData, Metadata};
+
+/// builds key maps in code instead of json, e.g. for plugins.
+/// The result is the same KeyMapData the json files are parsed into, pass it to KeyParser::from_data.
+/// when, priority and metadata modify the command or key map added last.
+/// ```
+/// use key_map::{GroupDispatch, KeyMapBuilder};
+/// let data = KeyMapBuilder::new()
+///   .function_sequence("save", &["write"])
+///   .function_sequence("save_all", &["write_all"]).when("modified")
+///   .group("smart_save", &["save_all", "save"], GroupDispatch::First)
+///   .bind("Normal", &["<c-s>"], "smart_save")
+///   .bind_modes(&["Normal", "Insert"], &["<c-q>"], "quit")
+///   .build();
+/// assert_eq!(data.commands.len(), 3);
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct KeyMapBuilder {
+  data: KeyMapData,
+  last: Option<Last>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Last {
+  Command,
+  KeyMap,
+}
+
+impl KeyMapBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// a command of functions and commands, like command_type Mixed in json
+  pub fn command(self, name: &str, commands: &[&str]) -> Self {
+    self.command_of_type(name, commands, CommandType::Mixed)
+  }
+  pub fn function_sequence(self, name: &str, functions: &[&str]) -> Self {
+    self.command_of_type(name, functions, CommandType::FunctionSequence)
+  }
+  pub fn group(self, name: &str, commands: &[&str], dispatch: GroupDispatch) -> Self {
+    let mut builder = self.command_of_type(name, commands, CommandType::CommandGroup);
+    builder.data.commands.last_mut().expect("command was just added").dispatch = dispatch;
+    builder
+  }
+  /// adds a command as it would be read from json
+  pub fn add_command(mut self, command: Command) -> Self {
+    self.data.commands.push(command);
+    self.last = Some(Last::Command);
+    self
+  }
+
+  /// binds keys to a command or function in mode
+  pub fn bind(self, mode: &str, keys: &[&str], command: &str) -> Self {
+    self.bind_modes(&[mode], keys, command)
+  }
+  pub fn bind_modes(self, modes: &[&str], keys: &[&str], command: &str) -> Self {
+    let mut key_map = KeyMap::new(keys, command);
+    key_map.mode = modes.iter().map(|m| m.to_string()).collect();
+    self.key_map(key_map)
+  }
+  /// adds a key map as it would be read from json
+  pub fn key_map(mut self, key_map: KeyMap) -> Self {
+    self.data.key_maps.push(key_map);
+    self.last = Some(Last::KeyMap);
+    self
+  }
+
+  pub fn when(mut self, when: &str) -> Self {
+    match self.last {
+      Some(Last::Command) => if let Some(command) = self.data.commands.last_mut() { command.when = when.to_owned() },
+      Some(Last::KeyMap) => if let Some(key_map) = self.data.key_maps.last_mut() { key_map.when = when.to_owned() },
+      None => {},
+    }
+    self
+  }
+  /// the priority of the last command, used by parent groups with GroupDispatch::Priority. Does nothing for key maps
+  pub fn priority(mut self, priority: i32) -> Self {
+    if let Some(Last::Command) = self.last {
+      if let Some(command) = self.data.commands.last_mut() {
+        command.priority = priority
+      }
+    }
+    self
+  }
+  pub fn metadata(mut self, metadata: Metadata) -> Self {
+    match self.last {
+      Some(Last::Command) => if let Some(command) = self.data.commands.last_mut() { command.metadata = metadata },
+      Some(Last::KeyMap) => if let Some(key_map) = self.data.key_maps.last_mut() { key_map.metadata = metadata },
+      None => {},
+    }
+    self
+  }
+
+  pub fn build(self) -> KeyMapData {
+    self.data
+  }
+
+  fn command_of_type(self, name: &str, commands: &[&str], command_type: CommandType) -> Self {
+    self.add_command(Command {
+      name: name.to_owned(),
+      commands: commands.iter().map(|c| c.to_string()).collect(),
+      command_type,
+      ..Command::default()
+    })
+  }
+}
+
+#[test]
+fn builder_matches_json_test() {
+  use crate::environment::DefaultEnvironment;
+  use crate::evaluation_tree::EvaluationTree;
+  use crate::evaluation_tree::from_key_map_data::try_into_evaluation_tree;
+  use crate::types::{FunctionString, KeyCode, Mode};
+
+  let json: KeyMapData = serde_json::from_str(r#"{
+    "commands": [
+      {"name": "save", "commands": ["function_one"], "command_type": "FunctionSequence", "title": "Save"},
+      {"name": "never", "commands": ["function_two"], "when": "false", "priority": 2},
+      {"name": "smart", "commands": ["never", "save"], "command_type": "CommandGroup", "dispatch": "Priority"}
+    ],
+    "key_maps": [
+      {"keys": ["s"], "command": "smart"},
+      {"keys": ["g", "s"], "command": "function_two", "mode": ["Normal", "Insert"]},
+      {"keys": ["g", "s"], "command": "save", "when": "false"}
+    ]
+  }"#).unwrap();
+  let built = KeyMapBuilder::new()
+    .function_sequence("save", &["function_one"]).metadata(Metadata { title: Some("Save".to_owned()), ..Metadata::default() })
+    .command("never", &["function_two"]).when("false").priority(2)
+    .group("smart", &["never", "save"], GroupDispatch::Priority)
+    .bind("Normal", &["s"], "smart")
+    .bind_modes(&["Normal", "Insert"], &["g", "s"], "function_two")
+    .bind("Normal", &["g", "s"], "save").when("false")
+    .build();
+
+  let env = DefaultEnvironment::with_functions(&["function_one", "function_two"]);
+  let json: EvaluationTree<Mode, KeyCode, FunctionString> = try_into_evaluation_tree(json, &env).unwrap();
+  let built: EvaluationTree<Mode, KeyCode, FunctionString> = try_into_evaluation_tree(built, &env).unwrap();
+
+  assert_eq!(built.command_infos(), json.command_infos());
+  for mode in [Mode::from("Normal"), Mode::from("Insert")] {
+    let mut built = built.which_key(&mode, &[], &env).unwrap();
+    let mut json = json.which_key(&mode, &[], &env).unwrap();
+    built.sort_by_key(|entry| format!("{:?}", entry.keys));
+    json.sort_by_key(|entry| format!("{:?}", entry.keys));
+    assert_eq!(built, json);
+  }
+  assert_eq!(built.evaluate(&[KeyCode::from("s")], &env), Ok(vec![&FunctionString::from("function_one")]));
+}
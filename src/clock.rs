@@ -0,0 +1,17 @@
+use std::time::Instant;
+
+/// source of the current time, used to resolve ambiguous key prefixes after a timeout.
+/// Replace the default SystemClock to control time in tests.
+/// Send and Sync keep the KeyParser movable to and shareable with other threads.
+pub trait Clock: Send + Sync {
+  fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> Instant {
+    Instant::now()
+  }
+}
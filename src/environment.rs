@@ -32,6 +32,14 @@ impl DefaultEnvironment {
   }
 }
 
+// for tests, an environment which knows the given functions
+#[cfg(test)]
+impl DefaultEnvironment {
+  pub(crate) fn with_functions(functions: &[&str]) -> Self {
+    Self {functions: functions.iter().map(|f| FunctionString::from(f.to_string())).collect(), ..Self::new()}
+  }
+}
+
 impl Environment<Mode, FunctionString> for DefaultEnvironment {}
 impl EnvFunctions<FunctionString> for DefaultEnvironment {
   fn is_function(&self, name: &str) -> bool {
@@ -0,0 +1,101 @@
+use std::fmt::Display;
+
+/// the error type returned by every fallible API of this crate.
+/// keys and modes are generic, therefore they are stored in their debug representation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyMapError {
+  /// the KeyParser was used before `init` succeeded
+  NotInitialized,
+  /// there are no key bindings for the current mode
+  UnknownMode(String),
+  /// the pressed keys do not lead to a command
+  InvalidKeySequence(Vec<String>),
+  /// the pressed keys are bound, but none of the bindings has a satisfied when expression
+  NoActiveBinding(Vec<String>),
+  /// key by key evaluation was terminated without any key pressed
+  NoKeyEntered,
+  /// a key map or command references a command which does not exist
+  CommandNotFound(String),
+  /// a command references a function which is not part of the environment
+  FunctionNotFound(String),
+  /// two commands share the same (prefixed) name
+  DuplicateCommand(String),
+  /// commands include each other in a cycle. The path starts and ends with the same command
+  CommandCycle(Vec<String>),
+  /// command expansion exceeded the maximal nesting depth
+  ExpansionDepthExceeded(usize),
+  /// a command without commands, only removals may leave them out
+  EmptyCommand(String),
+  /// the command exists, but resolves to no functions. E.g. because its when expression is not satisfied
+  NoFunctions(String),
+  /// a key map directory or file could not be read
+  Io { path: String, message: String },
+  /// a path is not valid utf-8 or not located below the key map root
+  InvalidPath(String),
+  /// a key map json file could not be deserialized
+  JsonParse { path: String, line: usize, column: usize, message: String },
+  /// a key map toml or yaml file could not be deserialized
+  Parse { path: String, line: usize, column: usize, message: String },
+  /// a key is not valid vim notation, e.g. "<C-" or "<Foo>"
+  InvalidKeyNotation { notation: String, message: String },
+  /// a when expression could not be evaluated
+  WhenExpression { expression: String, message: String },
+  /// key map data could not be serialized, e.g. while exporting
+  Serialize(String),
+  /// several errors collected at once, e.g. while parsing all commands
+  Multiple(Vec<KeyMapError>),
+}
+
+impl KeyMapError {
+  pub(crate) fn invalid_key_sequence<K: std::fmt::Debug>(keys: &[K]) -> Self {
+    KeyMapError::InvalidKeySequence(keys.iter().map(|k| format!("{k:?}")).collect())
+  }
+  pub(crate) fn no_active_binding<K: std::fmt::Debug>(keys: &[K]) -> Self {
+    KeyMapError::NoActiveBinding(keys.iter().map(|k| format!("{k:?}")).collect())
+  }
+  pub(crate) fn unknown_mode<M: std::fmt::Debug>(mode: &M) -> Self {
+    KeyMapError::UnknownMode(format!("{mode:?}"))
+  }
+  pub(crate) fn io(path: &std::path::Path, error: std::io::Error) -> Self {
+    KeyMapError::Io { path: path.display().to_string(), message: error.to_string() }
+  }
+  /// Ok if there are no errors, the error itself if there is exactly one and Multiple otherwise
+  pub(crate) fn collect(mut errors: Vec<KeyMapError>) -> Result<(), KeyMapError> {
+    match errors.len() {
+      0 => Ok(()),
+      1 => Err(errors.remove(0)),
+      _ => Err(KeyMapError::Multiple(errors)),
+    }
+  }
+}
+
+impl Display for KeyMapError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      KeyMapError::NotInitialized => write!(f, "no evaluation tree. KeyParser is not initialized. Potentially due to some flawed key map json"),
+      KeyMapError::UnknownMode(mode) => write!(f, "No keybindings for mode: {mode}"),
+      KeyMapError::InvalidKeySequence(keys) => write!(f, "Invalid key combination: [{}]", keys.join(", ")),
+      KeyMapError::NoActiveBinding(keys) => write!(f, "no binding with satisfied when expression for: [{}]", keys.join(", ")),
+      KeyMapError::NoKeyEntered => write!(f, "a node should be selected, probably no key entered"),
+      KeyMapError::CommandNotFound(name) => write!(f, "command not found: {name}"),
+      KeyMapError::FunctionNotFound(name) => write!(f, "function not found: {name}"),
+      KeyMapError::DuplicateCommand(name) => write!(f, "duplicate command name: {name}"),
+      KeyMapError::CommandCycle(path) => write!(f, "command cycle: {}", path.join(" -> ")),
+      KeyMapError::ExpansionDepthExceeded(depth) => write!(f, "command expansion exceeded the maximal depth of {depth}"),
+      KeyMapError::EmptyCommand(name) => write!(f, "command {name} has no commands"),
+      KeyMapError::NoFunctions(name) => write!(f, "No functions found for command: {name}"),
+      KeyMapError::Io { path, message } => write!(f, "{path}: {message}"),
+      KeyMapError::InvalidPath(path) => write!(f, "invalid key map path: {path}"),
+      KeyMapError::JsonParse { path, line, column, message } | KeyMapError::Parse { path, line, column, message } => write!(f, "{path}:{line}:{column}: {message}"),
+      KeyMapError::InvalidKeyNotation { notation, message } => write!(f, "invalid key \"{notation}\": {message}"),
+      KeyMapError::WhenExpression { expression, message } => write!(f, "when expression \"{expression}\" failed: {message}"),
+      KeyMapError::Serialize(message) => write!(f, "could not serialize key maps: {message}"),
+      KeyMapError::Multiple(errors) => {
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        write!(f, "{}", messages.join("\n"))
+      }
+    }
+  }
+}
+
+impl std::error::Error for KeyMapError {}
@@ -1,46 +1,120 @@
 pub(crate) mod command_execution;
 pub(crate) mod when_expression;
 pub(crate) mod from_key_map_data;
+pub(crate) mod which_key;
+pub(crate) mod reverse_lookup;
 
-use std::{collections::HashMap, fmt::format};
+use std::{collections::HashMap, fmt::format, time::{Duration, Instant}};
 use command_execution::{Command, CommandName};
+use when_expression::{Condition, WhenFallback};
 use crate::{environment::{self, EnvMode, EnvVariables}, types::{FunctionString, KeyCode, Mode}, Function, Key};
-use crate::Environment;
+use crate::{Environment, KeyMapError};
+use crate::json_parser::Metadata;
 
 
 #[derive(Debug)]
 pub struct EvaluationTree<M: Key, K: Key, F: Function> {
   tree: HashMap<M, KeyMapNode<K>>,
   commands: HashMap<String, Command<F>>,
+  functions: HashMap<String, F>, // functions bound directly by key maps
   pressed: Vec<K>,
   current_node: Option<KeyMapNode<K>>,
+  pending_since: Option<Instant>, // set while the current node has a binding and further keys
+  pub(crate) when_fallback: WhenFallback,
 }
 
 impl<M: Key, K: Key, F: Function> EvaluationTree<M, K, F> {
   fn new() -> Self {
-    Self { tree: HashMap::new(), commands: HashMap::new(), pressed: Vec::new(), current_node: None }
+    Self { tree: HashMap::new(), commands: HashMap::new(), functions: HashMap::new(), pressed: Vec::new(), current_node: None, pending_since: None, when_fallback: WhenFallback::default() }
   }
 
   fn add(&mut self, mode: M, key_map_node: KeyMapNode<K>) {
     self.tree.insert(mode, key_map_node);
   }
 
-  pub fn evaluate<E: Environment<M, F>>(&self, keys: &[K], environment: &E) -> Result<Vec<&F>, String>{
+  pub fn evaluate<E: Environment<M, F>>(&self, keys: &[K], environment: &E) -> Result<Vec<&F>, KeyMapError>{
     let mode = &environment.get_mode();
-    let name = self.tree.get(mode).ok_or(format!("mode should have some keybindings: {mode:?} has none"))?.evaluate(keys)?;
-    self.get_functions(name, environment)
+    let node = self.tree.get(mode).ok_or(KeyMapError::unknown_mode(mode))?.evaluate(keys)?;
+    self.resolve_node(node, keys, environment)
   }
 
-  fn get_functions<E: EnvVariables>(&self, name: &str, environment: &E) -> Result<Vec<&F>, String>{
-    let command = self.commands.get(name).ok_or(format!("command not found: {name}"))?;
-    let functions = command.execute(&self.commands, environment);
+  /// selects the binding of the node whose when expression is satisfied.
+  /// later key maps override earlier ones, therefore the last satisfied binding wins.
+  fn resolve_node<E: EnvVariables>(&self, node: &KeyMapNode<K>, keys: &[K], environment: &E) -> Result<Vec<&F>, KeyMapError>{
+    if node.bindings.is_empty() {
+      return Err(KeyMapError::invalid_key_sequence(keys));
+    }
+    match self.active_binding(node, environment)? {
+      Some(binding) => self.resolve(binding, environment),
+      None => Err(KeyMapError::no_active_binding(keys)),
+    }
+  }
+
+  fn active_binding<'a, E: EnvVariables>(&self, node: &'a KeyMapNode<K>, environment: &E) -> Result<Option<&'a Binding>, KeyMapError>{
+    for guarded in node.bindings.iter().rev() {
+      if guarded.condition.is_satisfied(environment, self.when_fallback)? {
+        return Ok(Some(&guarded.binding));
+      }
+    }
+    Ok(None)
+  }
+
+  fn resolve<E: EnvVariables>(&self, binding: &Binding, environment: &E) -> Result<Vec<&F>, KeyMapError>{
+    match binding {
+      Binding::Command(name) => self.get_functions(name, environment),
+      Binding::Function(name) => self.functions.get(name).map(|f| vec![f]).ok_or(KeyMapError::FunctionNotFound(name.to_owned())),
+    }
+  }
+
+  fn get_functions<E: EnvVariables>(&self, name: &str, environment: &E) -> Result<Vec<&F>, KeyMapError>{
+    let command = self.commands.get(name).ok_or(KeyMapError::CommandNotFound(name.to_owned()))?;
+    let functions = command.execute(&self.commands, environment, self.when_fallback)?;
     if functions.len() == 0 {
-      Err(format!("No functions found for command: {name}"))
+      Err(KeyMapError::NoFunctions(name.to_owned()))
     } else {
       Ok(functions)
     }
   }
   
+  pub(crate) fn commands(&self) -> impl Iterator<Item = &String> {
+    self.commands.keys()
+  }
+
+  pub(crate) fn command_metadata(&self, name: &str) -> Option<&Metadata> {
+    self.commands.get(name).map(|command| command.metadata())
+  }
+
+  pub(crate) fn pressed(&self) -> &[K] {
+    &self.pressed
+  }
+
+  /// cancels the pending key sequence
+  pub(crate) fn reset(&mut self) {
+    self.pressed = Vec::new();
+    self.current_node = None;
+    self.pending_since = None;
+  }
+
+  /// all keys which can follow the pressed keys, in no particular order
+  pub(crate) fn next_keys<E: Environment<M, F>>(&self, environment: &E) -> Result<Vec<NextKey<K>>, KeyMapError> {
+    let node = match self.current_node {
+      Some(ref node) => node,
+      None => match self.tree.get(&environment.get_mode()) {
+        Some(node) => node,
+        None => return Ok(Vec::new()),
+      },
+    };
+    let mut next_keys = Vec::new();
+    for (key, next) in node.next.iter().flatten() {
+      next_keys.push(NextKey {
+        key: key.to_owned(),
+        binding: self.active_binding(next, environment)?.cloned(),
+        is_prefix: next.next.as_ref().is_some_and(|n| !n.is_empty()),
+      });
+    }
+    Ok(next_keys)
+  }
+
   pub fn has_next<E: EnvMode<M>>(&self, key: &K, environment: &E) -> bool {
     if let Some(ref node) = self.current_node{
       node.get_next(key).is_some()
@@ -51,69 +125,125 @@ impl<M: Key, K: Key, F: Function> EvaluationTree<M, K, F> {
     }
   }
   
-  pub fn enter_key<E: Environment<M, F>>(&mut self, key: &K, environment: &E) -> Result<Option<Vec<&F>>, String> {
+  pub fn enter_key<E: Environment<M, F>>(&mut self, key: &K, environment: &E, now: Instant) -> Result<Option<Vec<&F>>, KeyMapError> {
+    self.pending_since = None;
     self.pressed.push(key.to_owned());
     if self.current_node.is_none() {
       self.current_node = self.tree.get(&environment.get_mode()).map(|node| node.to_owned());
     }
-    let node = self.current_node.take().ok_or(&format!("No keybindings for mode: {:?}", environment.get_mode()))?;
+    let node = match self.current_node.take() {
+      Some(node) => node,
+      None => {
+        self.pressed = Vec::new();
+        return Err(KeyMapError::unknown_mode(&environment.get_mode()));
+      }
+    };
     if let Some(next) = node.get_next(key) { // next is none unless its set again
 
-      if next.next.is_none() {
-        self.pressed = Vec::new();
-        return self.get_functions(next.evaluate(&[])?, environment).map(|x| Some(x));
+      if next.next.as_ref().is_none_or(|n| n.is_empty()) {
+        let pressed = std::mem::take(&mut self.pressed);
+        return self.resolve_node(&next, &pressed, environment).map(|x| Some(x));
       }
       
+      if !next.bindings.is_empty() {
+        self.pending_since = Some(now); // ambiguous prefix, may be resolved by a timeout
+      }
       self.current_node = Some(next); // set the next node
 
     } else {
-      let msg = format!("Invalid key combination: {:?}, {:?}", self.pressed, key);
+      let error = KeyMapError::invalid_key_sequence(&self.pressed);
       self.pressed = Vec::new();
-      return Err(msg);
+      return Err(error);
     }
     
     Ok(None)
   }
   
-  pub(crate) fn enter_key_terminate<E: EnvVariables>(&mut self, environment: &E) -> Result<Option<Vec<&F>>, String> {
-        let node = self.current_node.take().ok_or(format!("a node should be selected, probably no key entered"))?;
-        self.pressed = Vec::new();
-        return self.get_functions(node.evaluate(&[])?, environment).map(|x| Some(x));
+  /// terminates an ambiguous prefix if it is pending for at least timeout
+  pub(crate) fn poll<E: EnvVariables>(&mut self, now: Instant, timeout: Duration, environment: &E) -> Result<Option<Vec<&F>>, KeyMapError> {
+    match self.pending_since {
+      Some(since) if now.saturating_duration_since(since) >= timeout => self.enter_key_terminate(environment),
+      _ => Ok(None),
+    }
+  }
+
+  pub(crate) fn enter_key_terminate<E: EnvVariables>(&mut self, environment: &E) -> Result<Option<Vec<&F>>, KeyMapError> {
+        self.pending_since = None;
+        let node = self.current_node.take().ok_or(KeyMapError::NoKeyEntered)?;
+        let pressed = std::mem::take(&mut self.pressed);
+        return self.resolve_node(&node, &pressed, environment).map(|x| Some(x));
     }
 }
 
 
 
+/// what a key map resolves to, depending on the KeyMapCommandType of the key map
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+  Command(String),
+  Function(String),
+}
+
+impl Binding {
+  /// the name of the command or function
+  pub fn name(&self) -> &str {
+    match self {
+      Binding::Command(name) | Binding::Function(name) => name,
+    }
+  }
+}
+
+/// a key which can be pressed next, see KeyParser::next_keys
+#[derive(Clone, Debug, PartialEq)]
+pub struct NextKey<K: Key> {
+  pub key: K,
+  /// the binding executed by this key in the current environment, None if the key is only a prefix
+  /// or none of its when expressions is satisfied
+  pub binding: Option<Binding>,
+  /// more keys can follow this key
+  pub is_prefix: bool,
+}
+
+impl<K: Key> NextKey<K> {
+  /// the key completes a key sequence, no more keys can follow
+  pub fn is_terminal(&self) -> bool {
+    !self.is_prefix
+  }
+}
+
+/// a binding which is only active if its when expression is satisfied
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct GuardedBinding {
+  pub(crate) binding: Binding,
+  pub(crate) condition: Condition,
+  pub(crate) metadata: Metadata,
+  pub(crate) layer: String, // the configuration layer the key map originates from
+}
+
 #[derive(Clone, Debug)]
 struct KeyMapNode<K: Key> {
   next: Option<HashMap<K, Box<KeyMapNode<K>>>>,
-  command: Option<String>,
+  bindings: Vec<GuardedBinding>,
 }
 
 impl<K: Key> KeyMapNode<K> {
   fn new_command(command: String) -> Self {
-    Self { next: None, command: Some(command) }
+    Self { next: None, bindings: vec![GuardedBinding { binding: Binding::Command(command), condition: Condition::default(), metadata: Metadata::default(), layer: String::new() }] }
   }
   fn new() -> Self {
-    Self { next: None, command: None }
+    Self { next: None, bindings: Vec::new() }
   }
   fn add(&mut self, key: K, node: KeyMapNode<K>) {
-    self.next.as_mut().unwrap().insert(key, Box::new(node));
+    self.next.get_or_insert_with(HashMap::new).insert(key, Box::new(node));
   }
-  fn evaluate(&self, keys: &[K]) -> Result<&String, String> {
-    if keys.len() <= 0 {
-      return match self.command {
-        Some(ref c) => Ok(c),
-        None => Err(format!("no command {keys:?}")),
-      }
+  fn evaluate(&self, keys: &[K]) -> Result<&KeyMapNode<K>, KeyMapError> {
+    let mut node = self;
+    for key in keys {
+      node = node.next.as_ref()
+        .and_then(|next| next.get(key))
+        .ok_or(KeyMapError::invalid_key_sequence(keys))?;
     }
-    if let Some(k) = &self.next {
-      println!("keys: {keys:?}");
-      return k.get(keys.first().expect(&format!("should never happen: {keys:?} len bigger than 0 but first is none")))
-      .ok_or(format!("key {keys:?} does not exist at this position in eval tree"))?
-      .evaluate(&keys[1..]);
-    }
-    Err(format!("no key at position in eval tree: {keys:?}"))
+    Ok(node)
   }
   
   fn get_next(&self, key: &K) -> Option<KeyMapNode<K>> {
@@ -122,9 +252,35 @@ impl<K: Key> KeyMapNode<K> {
         } else { None } 
     }
     
-    fn insert_raw_data(&mut self, raw_keys: &[String], raw_command: &str) {
+    /// removes the bindings at keys, only those bound to command if it is set. Nodes left without bindings and continuations are dropped
+    fn remove_bindings(&mut self, keys: &[K], command: Option<&str>) -> bool {
+      let (key, rest) = match keys.split_first() {
+        Some(split) => split,
+        None => {
+          let count = self.bindings.len();
+          self.bindings.retain(|b| command.is_some_and(|c| c != b.binding.name()));
+          return self.bindings.len() != count;
+        }
+      };
+      let next = match self.next.as_mut() {
+        Some(next) => next,
+        None => return false,
+      };
+      let removed = next.get_mut(key).is_some_and(|node| node.remove_bindings(rest, command));
+      if next.get(key).is_some_and(|node| node.bindings.is_empty() && node.next.is_none()) {
+        next.remove(key);
+      }
+      if next.is_empty() {
+        self.next = None; // a leaf again, its binding is executed without waiting for more keys
+      }
+      removed
+    }
+
+    fn insert_raw_data(&mut self, raw_keys: &[String], raw_command: &GuardedBinding) {
         if raw_keys.len() <= 0 {
-          self.command = Some(raw_command.to_owned());
+          // a binding with the same when expression is overridden
+          self.bindings.retain(|b| b.condition != raw_command.condition);
+          self.bindings.push(raw_command.to_owned());
         } else {
           let next = self.next.take();
           let mut next = match next {
@@ -149,6 +305,15 @@ impl<K: Key> From<CommandName> for KeyMapNode<K> {
 }
 
 
+// for tests, the tree of an inline json key map and the environment it was built with
+#[cfg(test)]
+pub(crate) fn test_tree(json: &str, functions: &[&str]) -> (EvaluationTree<Mode, KeyCode, FunctionString>, environment::DefaultEnvironment) {
+  let env = environment::DefaultEnvironment::with_functions(functions);
+  let raw: crate::json_parser::KeyMapData = serde_json::from_str(json).unwrap();
+  (from_key_map_data::try_into_evaluation_tree(raw, &env).unwrap(), env)
+}
+
+
 #[test]
 fn evaluate_evaluation_tree_test() {
   let mut tree: EvaluationTree<Mode, KeyCode, FunctionString> = EvaluationTree::new();
@@ -156,7 +321,10 @@ fn evaluate_evaluation_tree_test() {
   let command = "command".to_owned();
   node.add(KeyCode::from("a"), command.into());
   tree.add(Mode::from("Normal"), node);
-  let c = tree.evaluate(&[KeyCode::from("a"), KeyCode::from("b")], &environment::DefaultEnvironment::new()).unwrap();
-  println!("{:?}", c);
+  let env = environment::DefaultEnvironment::new();
+  let c = tree.evaluate(&[KeyCode::from("a"), KeyCode::from("b")], &env);
+  assert_eq!(c, Err(KeyMapError::InvalidKeySequence(vec!["KeyCode(\"a\")".to_owned(), "KeyCode(\"b\")".to_owned()])));
+  let c = tree.evaluate(&[KeyCode::from("a")], &env);
+  assert_eq!(c, Err(KeyMapError::CommandNotFound("command".to_owned())));
 }
 
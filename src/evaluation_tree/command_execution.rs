@@ -1,45 +1,153 @@
 use std::collections::HashMap;
 
 use crate::environment::{EnvVariables};
+use crate::json_parser::{GroupDispatch, Metadata};
+use crate::KeyMapError;
 
 use super::{Function};
-use super::when_expression::Condition;
+use super::when_expression::{Condition, WhenFallback};
 
 #[derive(Debug, Default)]
 pub(crate) struct Command<F: Function> {
   condition: Condition,
   values: Vec<FunctionOrCommandName<F>>,
+  dispatch: GroupDispatch,
+  priority: i32,
+  metadata: Metadata,
 }
 
+/// nested commands are expanded at most this deep. Cycles are rejected while parsing, this is only a safety net.
+pub(crate) const MAX_EXPANSION_DEPTH: usize = 64;
+
 impl<F: Function> Command<F> {
-  pub(crate) fn execute<'a, E: EnvVariables>(&'a self, conglomerates: &'a HashMap<CommandName, Command<F>>, environment: &E) -> Vec<&F> {
-
-    if self.condition.is_satisfied(environment) {
-      self.values.iter().map(|value| {
-
-        match value {
-          FunctionOrCommandName::CommandName(command_name) => {
-            let name = command_name.to_string();
-            let conglomerate = conglomerates.get(&name)
-            .ok_or("command not found. this should never appear, since during parsing such errors are caught").unwrap();
-            conglomerate.execute(conglomerates, environment)
-          }
-          FunctionOrCommandName::Function(function) => vec![function]
+  pub(crate) fn execute<'a, E: EnvVariables>(&'a self, conglomerates: &'a HashMap<CommandName, Command<F>>, environment: &E, fallback: WhenFallback) -> Result<Vec<&'a F>, KeyMapError> {
+    self.execute_with_depth(conglomerates, environment, fallback, 0)
+  }
+
+  fn execute_with_depth<'a, E: EnvVariables>(&'a self, conglomerates: &'a HashMap<CommandName, Command<F>>, environment: &E, fallback: WhenFallback, depth: usize) -> Result<Vec<&'a F>, KeyMapError> {
+    if depth > MAX_EXPANSION_DEPTH {
+      return Err(KeyMapError::ExpansionDepthExceeded(MAX_EXPANSION_DEPTH));
+    }
+
+    if !self.condition.is_satisfied(environment, fallback)? {
+      return Ok(Vec::new());
+    }
+
+    if self.dispatch != GroupDispatch::All {
+      return match self.select(conglomerates, environment, fallback)? {
+        Some((_, conglomerate)) => conglomerate.execute_with_depth(conglomerates, environment, fallback, depth + 1),
+        None => Ok(Vec::new()),
+      };
+    }
+
+    let mut functions = Vec::new();
+    for value in &self.values {
+
+      match value {
+        FunctionOrCommandName::CommandName(command_name) => {
+          let conglomerate = conglomerates.get(command_name)
+          .ok_or(KeyMapError::CommandNotFound(command_name.to_owned()))?;
+          functions.extend(conglomerate.execute_with_depth(conglomerates, environment, fallback, depth + 1)?);
         }
+        FunctionOrCommandName::Function(function) => functions.push(function)
+      }
 
-      }).flatten().collect()
+    }
+    Ok(functions)
+  }
 
-    } else {
-      Vec::new()
+  /// the only sub command a First or Priority group executes, None if no sub command has a satisfied when expression
+  fn select<'a, E: EnvVariables>(&self, conglomerates: &'a HashMap<CommandName, Command<F>>, environment: &E, fallback: WhenFallback) -> Result<Option<(&'a CommandName, &'a Command<F>)>, KeyMapError> {
+    let mut selected: Option<(&CommandName, &Command<F>)> = None;
+    for command_name in self.command_names() {
+      let (name, conglomerate) = conglomerates.get_key_value(command_name)
+      .ok_or(KeyMapError::CommandNotFound(command_name.to_owned()))?;
+      if !conglomerate.condition.is_satisfied(environment, fallback)? {
+        continue;
+      }
+      if self.dispatch == GroupDispatch::First {
+        return Ok(Some((name, conglomerate)));
+      }
+      if selected.is_none_or(|(_, s)| conglomerate.priority > s.priority) {
+        selected = Some((name, conglomerate));
+      }
+    }
+    Ok(selected)
+  }
+
+  /// names of the commands this command expands into, respecting the dispatch of groups.
+  /// With an environment a First or Priority group expands into the selected command only,
+  /// without one into every command it selects in some environment.
+  pub(crate) fn expanded_command_names<'a, E: EnvVariables>(&'a self, conglomerates: &'a HashMap<CommandName, Command<F>>, environment: Option<&E>, fallback: WhenFallback) -> Vec<&'a CommandName> {
+    if self.dispatch == GroupDispatch::All {
+      return self.command_names().collect();
+    }
+    if let Some(environment) = environment {
+      return self.select(conglomerates, environment, fallback).ok().flatten().map(|(name, _)| name).into_iter().collect();
     }
+    // a command is never selected if an unconditional command wins against it
+    let candidates: Vec<(usize, &CommandName, Option<&Command<F>>)> = self.command_names().enumerate()
+      .map(|(index, name)| (index, name, conglomerates.get(name)))
+      .collect();
+    let wins = |winner: (usize, &Command<F>), loser: (usize, Option<&Command<F>>)| match self.dispatch {
+      GroupDispatch::Priority => loser.1.is_none_or(|l| winner.1.priority > l.priority || (winner.1.priority == l.priority && winner.0 < loser.0)),
+      _ => winner.0 < loser.0,
+    };
+    candidates.iter()
+      .filter(|(index, _, command)| !candidates.iter().any(|(other, _, winner)| other != index
+        && winner.is_some_and(|winner| winner.condition.is_always_true() && wins((*other, winner), (*index, *command)))))
+      .map(|(_, name, _)| *name)
+      .collect()
+  }
+
+  pub(crate) fn condition(&self) -> &Condition {
+    &self.condition
+  }
+
+  /// functions this command includes directly
+  pub(crate) fn functions(&self) -> impl Iterator<Item = &F> {
+    self.values.iter().filter_map(|value| match value {
+      FunctionOrCommandName::Function(function) => Some(function),
+      FunctionOrCommandName::CommandName(_) => None,
+    })
+  }
+
+  /// names of all commands this command expands into
+  pub(crate) fn command_names(&self) -> impl Iterator<Item = &CommandName> {
+    self.values.iter().filter_map(|value| match value {
+      FunctionOrCommandName::CommandName(name) => Some(name),
+      FunctionOrCommandName::Function(_) => None,
+    })
   }
 
   pub(crate) fn new(values: Vec<FunctionOrCommandName<F>>, when: Condition) -> Self {
         Self {
             condition: when,
             values,
+            dispatch: GroupDispatch::default(),
+            priority: 0,
+            metadata: Metadata::default(),
         }
     }
+
+  pub(crate) fn with_metadata(mut self, metadata: Metadata) -> Self {
+    self.metadata = metadata;
+    self
+  }
+
+  pub(crate) fn metadata(&self) -> &Metadata {
+    &self.metadata
+  }
+
+  pub(crate) fn with_dispatch(mut self, dispatch: GroupDispatch) -> Self {
+    self.dispatch = dispatch;
+    self
+  }
+
+  pub(crate) fn with_priority(mut self, priority: i32) -> Self {
+    self.priority = priority;
+    self
+  }
 }
 
 #[derive(Debug)]
@@ -48,4 +156,57 @@ pub(crate) enum FunctionOrCommandName<F: Function> {
   Function(F),
 }
 
-pub(crate) type CommandName = String;
\ No newline at end of file
+pub(crate) type CommandName = String;
+
+/// finds all cycles in the graph of commands referencing other commands.
+/// each cycle is returned as path which starts and ends with the same command, e.g. [a, b, a].
+pub(crate) fn find_cycles(graph: &HashMap<&str, Vec<&str>>) -> Vec<Vec<String>> {
+  let mut names: Vec<&str> = graph.keys().copied().collect();
+  names.sort();
+  let mut finished: Vec<&str> = Vec::new();
+  let mut cycles = Vec::new();
+  for name in names {
+    visit(name, graph, &mut Vec::new(), &mut finished, &mut cycles);
+  }
+  cycles
+}
+
+fn visit<'a>(name: &'a str, graph: &HashMap<&'a str, Vec<&'a str>>, path: &mut Vec<&'a str>, finished: &mut Vec<&'a str>, cycles: &mut Vec<Vec<String>>) {
+  if finished.contains(&name) {
+    return;
+  }
+  if let Some(position) = path.iter().position(|n| *n == name) {
+    let mut cycle: Vec<String> = path[position..].iter().map(|n| n.to_string()).collect();
+    cycle.push(name.to_owned());
+    cycles.push(cycle);
+    return;
+  }
+  path.push(name);
+  for next in graph.get(name).into_iter().flatten() {
+    visit(next, graph, path, finished, cycles);
+  }
+  path.pop();
+  finished.push(name);
+}
+
+#[test]
+fn find_cycles_test() {
+  let graph: HashMap<&str, Vec<&str>> = HashMap::from([
+    ("a", vec!["b"]),
+    ("b", vec!["c", "a"]),
+    ("c", vec![]),
+    ("d", vec!["d"]),
+  ]);
+  let cycles = find_cycles(&graph);
+  assert_eq!(cycles, vec![vec!["a", "b", "a"], vec!["d", "d"]]);
+}
+
+#[test]
+fn expansion_depth_test() {
+  use crate::environment::DefaultEnvironment;
+  use crate::types::FunctionString;
+  let mut commands: HashMap<CommandName, Command<FunctionString>> = HashMap::new();
+  commands.insert("a".to_owned(), Command::new(vec![FunctionOrCommandName::CommandName("a".to_owned())], Condition::default()));
+  let result = commands["a"].execute(&commands, &DefaultEnvironment::new(), WhenFallback::Error);
+  assert_eq!(result, Err(KeyMapError::ExpansionDepthExceeded(MAX_EXPANSION_DEPTH)));
+}
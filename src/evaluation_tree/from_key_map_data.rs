@@ -3,62 +3,163 @@ use std::collections::HashMap;
 use command_execution::CommandName;
 use environment::EnvFunctions;
 
-use crate::Environment;
-use crate::json_parser::{self, KeyMapData, CommandType};
+use crate::{Environment, KeyMapError};
+use crate::json_parser::{self, KeyMapData, CommandType, KeyMapCommandType};
 use super::*;
-use super::command_execution::{FunctionOrCommandName};
+use super::command_execution::{find_cycles, FunctionOrCommandName};
 use super::when_expression::Condition;
 
-pub(crate) fn try_into_evaluation_tree<M: Key, K: Key, F: Function, E: Environment<M, F>>(raw: KeyMapData, environment: &E) -> Result<EvaluationTree<M, K, F>, String> {
+/// builds the tree of raw data whose removals are applied already, see apply_removals
+pub(crate) fn try_into_evaluation_tree<M: Key, K: Key, F: Function, E: Environment<M, F>>(raw: KeyMapData, environment: &E) -> Result<EvaluationTree<M, K, F>, KeyMapError> {
   let mut tree = EvaluationTree::new();
+  let raw_command_names: Vec<CommandName> = raw.commands.iter().map(|c| CommandName::from(&c.name)).collect();
   tree.commands = try_into_commands::<F, E>(raw.commands, &environment)?;
 
+  let mut errors = Vec::new();
   for raw_key_map in raw.key_maps {
-    println!("mode: {raw_key_map:?}");
-    for mode in raw_key_map.mode {
-      tree.tree.entry(M::from(mode))
-      .and_modify(|node| node.insert_raw_data(&raw_key_map.keys, &raw_key_map.command))
-      .or_insert({let mut n = KeyMapNode::new();
-        n.insert_raw_data(&raw_key_map.keys, &raw_key_map.command);
-        n});
+    match tree.insert_key_map(&raw_key_map, &raw_command_names, environment) {
+      Ok(()) => {},
+      Err(KeyMapError::Multiple(key_map_errors)) => errors.extend(key_map_errors),
+      Err(error) => errors.push(error),
     }
   }
+  KeyMapError::collect(errors)?;
 
   Ok(tree)
 }
 
+impl<M: Key, K: Key, F: Function> EvaluationTree<M, K, F> {
+  /// resolves the key map and binds its keys in all its modes
+  pub(crate) fn insert_key_map<E: EnvFunctions<F>>(&mut self, raw_key_map: &json_parser::KeyMap, raw_command_names: &[CommandName], environment: &E) -> Result<(), KeyMapError> {
+    let binding = key_map_to_binding::<F, E>(raw_key_map, raw_command_names, environment);
+    let condition = Condition::new(&raw_key_map.when);
+    let binding = match (binding, condition) {
+      (Ok(binding), Ok(condition)) => GuardedBinding { binding, condition, metadata: raw_key_map.metadata.clone(), layer: raw_key_map.layer.clone() },
+      (binding, condition) => return KeyMapError::collect(binding.err().into_iter().chain(condition.err()).collect()),
+    };
+    if let Binding::Function(ref name) = binding.binding {
+      self.functions.entry(name.to_owned()).or_insert_with(|| F::from(name.to_owned()));
+    }
+    for mode in &raw_key_map.mode {
+      self.tree.entry(M::from(mode.to_owned()))
+        .or_insert_with(KeyMapNode::new)
+        .insert_raw_data(&raw_key_map.keys, &binding);
+    }
+    Ok(())
+  }
+
+  /// removes the bindings of the keys in the modes of the key map, only those bound to its command if it is set.
+  /// Returns whether any binding was removed
+  pub(crate) fn remove_key_map(&mut self, raw_key_map: &json_parser::KeyMap) -> bool {
+    let keys: Vec<K> = raw_key_map.keys.iter().map(|k| K::from(k.to_owned())).collect();
+    let command = if raw_key_map.command.is_empty() { None } else { Some(raw_key_map.command.as_str()) };
+    let mut removed = false;
+    for mode in &raw_key_map.mode {
+      if let Some(node) = self.tree.get_mut(&M::from(mode.to_owned())) {
+        removed |= node.remove_bindings(&keys, command);
+      }
+    }
+    removed
+  }
+}
+
+/// applies command removals and key unbindings to the entries defined before them, in order.
+/// Removed commands take their key maps with them, unless the command is defined again later.
+/// Mixed key maps are kept if a function of the same name exists, like key_map_to_binding they resolve to it.
+/// Keys are compared as K, e.g. a KeyPress unbind of "<c-k>" removes a binding of "<C-k>" or "ctrl+k".
+pub(crate) fn apply_removals<K: Key, F: Function, E: EnvFunctions<F>>(raw: &mut KeyMapData, env_functions: &E) {
+  let parse = |keys: &[String]| keys.iter().map(|k| K::from(k.to_owned())).collect::<Vec<K>>();
+  let mut commands: Vec<json_parser::Command> = Vec::new();
+  let mut removed: Vec<CommandName> = Vec::new();
+  for raw_command in raw.commands.drain(..) {
+    if raw_command.remove {
+      commands.retain(|c| c.name != raw_command.name);
+      removed.push(raw_command.name);
+    } else {
+      commands.push(raw_command);
+    }
+  }
+  raw.commands = commands;
+  removed.retain(|name| !raw.commands.iter().any(|c| c.name == *name));
+
+  let mut key_maps: Vec<json_parser::KeyMap> = Vec::new();
+  for mut raw_key_map in raw.key_maps.drain(..) {
+    if let Some(command) = raw_key_map.command.strip_prefix('-') { // vs code style "-command"
+      raw_key_map.command = command.to_owned();
+      raw_key_map.unbind = true;
+    }
+    if raw_key_map.unbind {
+      let command = if raw_key_map.command.is_empty() { None } else { Some(&raw_key_map.command) };
+      let keys = parse(&raw_key_map.keys);
+      key_maps.iter_mut()
+        .filter(|k| command.is_none_or(|c| *c == k.command) && parse(&k.keys) == keys)
+        .for_each(|k| k.mode.retain(|m| !raw_key_map.mode.contains(m)));
+      key_maps.retain(|k| !k.mode.is_empty());
+    } else {
+      key_maps.push(raw_key_map);
+    }
+  }
+  key_maps.retain(|k| match k.command_type {
+    KeyMapCommandType::Function => true,
+    KeyMapCommandType::Mixed if env_functions.is_function(&k.command) => true,
+    _ => !removed.contains(&k.command),
+  });
+  raw.key_maps = key_maps;
+}
+
+/// resolves the target of a key map according to its KeyMapCommandType. Mixed prefers commands over functions.
+pub(crate) fn key_map_to_binding<F: Function, E: EnvFunctions<F>>(raw_key_map: &json_parser::KeyMap, raw_command_names: &[CommandName], env_functions: &E) -> Result<Binding, KeyMapError> {
+  let target = &raw_key_map.command;
+  let is_command = raw_command_names.contains(target);
+  match raw_key_map.command_type {
+    KeyMapCommandType::Command if is_command => Ok(Binding::Command(target.to_owned())),
+    KeyMapCommandType::Command => Err(KeyMapError::CommandNotFound(target.to_owned())),
+    KeyMapCommandType::Function if env_functions.is_function(target) => Ok(Binding::Function(target.to_owned())),
+    KeyMapCommandType::Function => Err(KeyMapError::FunctionNotFound(target.to_owned())),
+    KeyMapCommandType::Mixed if is_command => Ok(Binding::Command(target.to_owned())),
+    KeyMapCommandType::Mixed if env_functions.is_function(target) => Ok(Binding::Function(target.to_owned())),
+    KeyMapCommandType::Mixed => Err(KeyMapError::CommandNotFound(target.to_owned())),
+  }
+}
+
 
-fn try_into_commands<F: Function, E: EnvFunctions<F>>(raw_commands:Vec<json_parser::Command>, environment: &E) -> Result<HashMap<String, Command<F>>, String> {
+fn try_into_commands<F: Function, E: EnvFunctions<F>>(raw_commands:Vec<json_parser::Command>, environment: &E) -> Result<HashMap<String, Command<F>>, KeyMapError> {
     let raw_command_names: Vec<CommandName> = raw_commands.iter().map(|c| CommandName::from(&c.name)).collect();
     let mut commands: HashMap<String, Command<F>> = HashMap::new();
 
     for raw_command in &raw_commands{
       let (command, errors) = raw_command_to_command::<F, E>(raw_command, &raw_command_names, environment);
       if commands.insert(raw_command.name.to_owned(), command).is_some() {
-        return Err(format!("duplicate command name: {}", raw_command.name));
+        return Err(KeyMapError::DuplicateCommand(raw_command.name.to_owned()));
       };
-      if errors.len() > 0 {
-        return Err(errors.join("\n"));
-      }
+      KeyMapError::collect(errors)?;
     }
+    let graph: HashMap<&str, Vec<&str>> = commands.iter()
+      .map(|(name, command)| (name.as_str(), command.command_names().map(|c| c.as_str()).collect()))
+      .collect();
+    KeyMapError::collect(find_cycles(&graph).into_iter().map(KeyMapError::CommandCycle).collect())?;
     Ok(commands)
   }
 
-fn raw_command_to_command<F: Function, E: EnvFunctions<F>>(raw_command: &json_parser::Command, raw_command_names: &Vec<CommandName>, env_functions: &E) -> (Command<F>, Vec<String>) {
+pub(crate) fn raw_command_to_command<F: Function, E: EnvFunctions<F>>(raw_command: &json_parser::Command, raw_command_names: &Vec<CommandName>, env_functions: &E) -> (Command<F>, Vec<KeyMapError>) {
   let mut errors = Vec::new();
-  match raw_command.command_type {
+  let condition = Condition::new(&raw_command.when).unwrap_or_else(|e| {errors.push(e); Condition::default()});
+  if raw_command.commands.is_empty() {
+    errors.push(KeyMapError::EmptyCommand(raw_command.name.to_owned()));
+  }
+  let (command, errors) = match raw_command.command_type {
     CommandType::FunctionSequence => {
       (Command::new(
-        raw_command.commands.iter().map(|f| {if !env_functions.is_function(f) {errors.push(format!("function not found: {}", f))};
+        raw_command.commands.iter().map(|f| {if !env_functions.is_function(f) {errors.push(KeyMapError::FunctionNotFound(f.to_owned()))};
           FunctionOrCommandName::Function(F::from(f.to_owned()))}).collect(),
-        Condition::new(&raw_command.when)),
+        condition),
       errors)
     }
     CommandType::CommandGroup => {
       (Command::new(
-        raw_command.commands.iter().map(|c| {if !raw_command_names.contains(c) {errors.push(format!("command not found: {}", c))};
+        raw_command.commands.iter().map(|c| {if !raw_command_names.contains(c) {errors.push(KeyMapError::CommandNotFound(c.to_owned()))};
         FunctionOrCommandName::CommandName(CommandName::from(c))}).collect(),
-        Condition::new(&raw_command.when)),
+        condition).with_dispatch(raw_command.dispatch),
       errors)
     },
     CommandType::Mixed => {
@@ -66,12 +167,198 @@ fn raw_command_to_command<F: Function, E: EnvFunctions<F>>(raw_command: &json_pa
         if raw_command_names.contains(c) {
           FunctionOrCommandName::CommandName(CommandName::from(c))
         } else {
-          if !env_functions.is_function(c) {errors.push(format!("function not found: {}", c))};
+          if !env_functions.is_function(c) {errors.push(KeyMapError::FunctionNotFound(c.to_owned()))};
           FunctionOrCommandName::Function(F::from(c.to_owned()))
         }
       ).collect(),
-      Condition::new(&raw_command.when)),
+      condition),
       errors)
     },
-  }
-}
\ No newline at end of file
+  };
+  (command.with_priority(raw_command.priority).with_metadata(raw_command.metadata.clone()), errors)
+}
+
+#[test]
+fn command_cycle_test() {
+  let raw: KeyMapData = serde_json::from_str(r#"{
+    "commands": [
+      {"name": "a", "commands": ["b"], "command_type": "CommandGroup"},
+      {"name": "b", "commands": ["function_one", "a"]}
+    ]
+  }"#).unwrap();
+  let env = environment::DefaultEnvironment::with_functions(&["function_one"]);
+  let error = try_into_commands::<FunctionString, _>(raw.commands, &env).unwrap_err();
+  assert_eq!(error, KeyMapError::CommandCycle(vec!["a".to_owned(), "b".to_owned(), "a".to_owned()]));
+  assert_eq!(error.to_string(), "command cycle: a -> b -> a");
+}
+
+#[test]
+fn key_map_command_type_test() {
+  let (tree, env) = test_tree(r#"{
+    "commands": [
+      {"name": "function_one", "commands": ["function_one", "function_two"], "command_type": "FunctionSequence"}
+    ],
+    "key_maps": [
+      {"keys": ["a"], "command": "function_one", "command_type": "Function"},
+      {"keys": ["b"], "command": "function_one", "command_type": "Command"},
+      {"keys": ["c"], "command": "function_one"},
+      {"keys": ["d"], "command": "function_two"}
+    ]
+  }"#, &["function_one", "function_two"]);
+  let evaluate = |key: &str| tree.evaluate(&[KeyCode::from(key)], &env).unwrap().iter().map(|f| f.to_string()).collect::<Vec<_>>();
+  assert_eq!(evaluate("a"), vec!["function_one"]);
+  assert_eq!(evaluate("b"), vec!["function_one", "function_two"]);
+  assert_eq!(evaluate("c"), vec!["function_one", "function_two"]);
+  assert_eq!(evaluate("d"), vec!["function_two"]);
+}
+
+#[test]
+fn key_map_command_type_error_test() {
+  let raw: KeyMapData = serde_json::from_str(r#"{
+    "key_maps": [
+      {"keys": ["a"], "command": "function_one", "command_type": "Command"},
+      {"keys": ["b"], "command": "missing", "command_type": "Function"}
+    ]
+  }"#).unwrap();
+  let env = environment::DefaultEnvironment::with_functions(&["function_one"]);
+  let error = try_into_evaluation_tree::<Mode, KeyCode, FunctionString, _>(raw, &env).unwrap_err();
+  assert_eq!(error, KeyMapError::Multiple(vec![
+    KeyMapError::CommandNotFound("function_one".to_owned()),
+    KeyMapError::FunctionNotFound("missing".to_owned()),
+  ]));
+}
+
+#[test]
+fn group_dispatch_test() {
+  let raw: KeyMapData = serde_json::from_str(r#"{
+    "commands": [
+      {"name": "never", "commands": ["function_one"], "when": "false"},
+      {"name": "low", "commands": ["function_two"], "priority": 1},
+      {"name": "high", "commands": ["function_three"], "priority": 5},
+      {"name": "all", "commands": ["never", "low", "high"], "command_type": "CommandGroup"},
+      {"name": "first", "commands": ["never", "low", "high"], "command_type": "CommandGroup", "dispatch": "First"},
+      {"name": "priority", "commands": ["never", "low", "high"], "command_type": "CommandGroup", "dispatch": "Priority"}
+    ]
+  }"#).unwrap();
+  let env = environment::DefaultEnvironment::with_functions(&["function_one", "function_two", "function_three"]);
+  let commands = try_into_commands::<FunctionString, _>(raw.commands, &env).unwrap();
+  let execute = |name: &str| commands[name].execute(&commands, &env, WhenFallback::Error).unwrap().iter().map(|f| f.to_string()).collect::<Vec<_>>();
+  assert_eq!(execute("all"), vec!["function_two", "function_three"]);
+  assert_eq!(execute("first"), vec!["function_two"]);
+  assert_eq!(execute("priority"), vec!["function_three"]);
+}
+
+#[test]
+fn key_map_when_test() {
+  let (tree, env) = test_tree(r#"{
+    "key_maps": [
+      {"keys": ["a"], "command": "function_one"},
+      {"keys": ["a"], "command": "function_two", "when": "false"},
+      {"keys": ["b"], "command": "function_one", "when": "false"}
+    ]
+  }"#, &["function_one", "function_two"]);
+  assert_eq!(tree.evaluate(&[KeyCode::from("a")], &env), Ok(vec![&FunctionString::from("function_one")]));
+  assert_eq!(tree.evaluate(&[KeyCode::from("b")], &env), Err(KeyMapError::NoActiveBinding(vec!["KeyCode(\"b\")".to_owned()])));
+}
+
+#[test]
+fn metadata_test() {
+  let (tree, env) = test_tree(r#"{
+    "commands": [
+      {"name": "save", "commands": ["function_one"], "title": "Save", "description": "saves the file", "category": "File", "tags": ["io"]}
+    ],
+    "key_maps": [
+      {"keys": ["s"], "command": "save", "description": "save with s", "tags": ["quick"]}
+    ]
+  }"#, &["function_one"]);
+  let metadata = tree.command_metadata("save").unwrap();
+  assert_eq!(metadata.title.as_deref(), Some("Save"));
+  assert_eq!(metadata.category.as_deref(), Some("File"));
+  let entries = tree.which_key(&Mode::from("Normal"), &[], &env).unwrap();
+  let merged = &entries[0].bindings[0].metadata;
+  assert_eq!(merged.description.as_deref(), Some("save with s"));
+  assert_eq!(merged.title.as_deref(), Some("Save"));
+  assert_eq!(merged.tags, vec!["quick".to_owned(), "io".to_owned()]);
+}
+
+#[test]
+fn removal_test() {
+  let raw: KeyMapData = serde_json::from_str(r#"{
+    "commands": [
+      {"name": "save", "commands": ["function_one"]},
+      {"name": "quit", "commands": ["function_two"]},
+      {"name": "quit", "remove": true},
+      {"name": "function_two", "commands": ["function_one"]},
+      {"name": "function_two", "remove": true}
+    ],
+    "key_maps": [
+      {"keys": ["a"], "command": "save", "mode": ["Normal", "Insert"]},
+      {"keys": ["b"], "command": "save"},
+      {"keys": ["q"], "command": "quit"},
+      {"keys": ["a"], "unbind": true, "mode": ["Insert"]},
+      {"keys": ["b"], "command": "-save"},
+      {"keys": ["b"], "command": "function_two"},
+      {"keys": ["f"], "command": "function_two"},
+      {"keys": ["g"], "command": "function_two", "command_type": "Command"}
+    ]
+  }"#).unwrap();
+  let mut env = environment::DefaultEnvironment::with_functions(&["function_one", "function_two"]);
+  env.set_mode(Mode::from("Insert"));
+  let mut raw = raw;
+  apply_removals::<KeyCode, FunctionString, _>(&mut raw, &env);
+  let tree: EvaluationTree<Mode, KeyCode, FunctionString> = try_into_evaluation_tree(raw, &env).unwrap();
+  assert!(tree.evaluate(&[KeyCode::from("a")], &env).is_err());
+  env.set_mode(Mode::from("Normal"));
+  assert_eq!(tree.evaluate(&[KeyCode::from("a")], &env), Ok(vec![&FunctionString::from("function_one")]));
+  assert_eq!(tree.evaluate(&[KeyCode::from("b")], &env), Ok(vec![&FunctionString::from("function_two")]));
+  assert!(tree.evaluate(&[KeyCode::from("q")], &env).is_err());
+  assert!(tree.execute_command("quit", &env).is_err());
+  // the mixed key map falls back to the function of the removed command's name, the command key map is removed
+  assert_eq!(tree.evaluate(&[KeyCode::from("f")], &env), Ok(vec![&FunctionString::from("function_two")]));
+  assert!(tree.evaluate(&[KeyCode::from("g")], &env).is_err());
+}
+
+#[test]
+fn removal_compares_parsed_keys_test() {
+  use crate::types::KeyPress;
+  let mut raw: KeyMapData = serde_json::from_str(r#"{
+    "key_maps": [
+      {"keys": "<C-k><C-c>", "command": "function_one"},
+      {"keys": ["<c-d>"], "command": "function_one"},
+      {"keys": "<c-k><c-c>", "unbind": true},
+      {"keys": "ctrl+d", "command": "-function_one"}
+    ]
+  }"#).unwrap();
+  let env = environment::DefaultEnvironment::with_functions(&["function_one"]);
+  apply_removals::<KeyPress, FunctionString, _>(&mut raw, &env);
+  assert!(raw.key_maps.is_empty());
+}
+
+#[test]
+fn empty_command_test() {
+  let raw: KeyMapData = serde_json::from_str(r#"{"commands": [{"name": "nothing"}]}"#).unwrap();
+  let env = environment::DefaultEnvironment::new();
+  let error = try_into_evaluation_tree::<Mode, KeyCode, FunctionString, _>(raw, &env).unwrap_err();
+  assert_eq!(error, KeyMapError::EmptyCommand("nothing".to_owned()));
+}
+
+#[test]
+fn insert_remove_key_map_test() {
+  let (mut tree, env) = test_tree(r#"{
+    "commands": [{"name": "save", "commands": ["function_one"]}],
+    "key_maps": [{"keys": ["g", "s"], "command": "save"}]
+  }"#, &["function_one", "function_two"]);
+  let commands = vec!["save".to_owned()];
+  let keys = [KeyCode::from("g"), KeyCode::from("s")];
+
+  let key_map = json_parser::KeyMap::new(&["g", "s"], "function_two");
+  tree.insert_key_map(&key_map, &commands, &env).unwrap();
+  assert_eq!(tree.evaluate(&keys, &env), Ok(vec![&FunctionString::from("function_two")]));
+  let error = tree.insert_key_map(&json_parser::KeyMap::new(&["x"], "missing"), &commands, &env).unwrap_err();
+  assert_eq!(error, KeyMapError::CommandNotFound("missing".to_owned()));
+
+  assert!(!tree.remove_key_map(&json_parser::KeyMap::new(&["g", "s"], "save")));
+  assert!(tree.remove_key_map(&json_parser::KeyMap::new(&["g", "s"], "")));
+  // the empty prefix g is removed as well
+  assert_eq!(tree.evaluate(&[KeyCode::from("g")], &env), Err(KeyMapError::invalid_key_sequence(&[KeyCode::from("g")])));
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+
+use crate::environment::EnvVariables;
+use crate::json_parser::Metadata;
+use crate::{Function, Key, KeyMapError};
+
+use super::{Binding, EvaluationTree, GuardedBinding, KeyMapNode};
+
+/// a command with its key bindings, see KeyParser::commands
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommandInfo<M: Key, K: Key> {
+  pub name: String,
+  pub metadata: Metadata,
+  /// all key sequences per mode which are bound directly to this command
+  pub bindings: HashMap<M, Vec<Vec<K>>>,
+}
+
+impl<M: Key, K: Key, F: Function> EvaluationTree<M, K, F> {
+  /// executes a command by name, without pressing keys
+  pub(crate) fn execute_command<E: EnvVariables>(&self, name: &str, environment: &E) -> Result<Vec<&F>, KeyMapError> {
+    self.get_functions(name, environment)
+  }
+
+  /// all commands sorted by name, with the key sequences bound to them
+  pub(crate) fn command_infos(&self) -> Vec<CommandInfo<M, K>> {
+    let mut infos: HashMap<&str, CommandInfo<M, K>> = self.commands.iter().map(|(name, command)| (name.as_str(), CommandInfo {
+      name: name.to_owned(),
+      metadata: command.metadata().to_owned(),
+      bindings: HashMap::new(),
+    })).collect();
+    for (mode, keys, guarded) in self.key_sequences() {
+      if let Binding::Command(ref name) = guarded.binding {
+        if let Some(info) = infos.get_mut(name.as_str()) {
+          let sequences = info.bindings.entry(mode.to_owned()).or_default();
+          if !sequences.contains(&keys) {
+            sequences.push(keys);
+          }
+        }
+      }
+    }
+    let mut infos: Vec<CommandInfo<M, K>> = infos.into_values().collect();
+    infos.sort_by(|a, b| a.name.cmp(&b.name));
+    infos
+  }
+
+  /// all key sequences of mode whose binding is the command or expands into it.
+  /// If active_only, only bindings which are executed in the current environment are followed:
+  /// key maps overridden by a later satisfied key map and commands with unsatisfied when expressions are skipped,
+  /// groups with First or Priority dispatch only expand into the selected command.
+  pub(crate) fn bindings_for_command<E: EnvVariables>(&self, name: &str, mode: &M, environment: &E, active_only: bool) -> Vec<Vec<K>> {
+    let target = ExpansionTarget::Command(name);
+    let environment = if active_only { Some(environment) } else { None };
+    let mut result: Vec<Vec<K>> = Vec::new();
+    for (binding_mode, keys, guarded) in self.key_sequences() {
+      if binding_mode != mode || result.contains(&keys) || !self.is_active(mode, &keys, guarded, environment) {
+        continue;
+      }
+      let found = match guarded.binding {
+        Binding::Command(ref command) => self.expands_into(command, &target, environment, &mut Vec::new()),
+        Binding::Function(_) => false,
+      };
+      if found {
+        result.push(keys);
+      }
+    }
+    result.sort_by_key(|keys| keys.len());
+    result
+  }
+
+  /// all key sequences of all modes which eventually yield the function.
+  /// If active_only, only key sequences whose evaluation in the current environment yields the function are returned.
+  pub(crate) fn bindings_for_function<E: EnvVariables>(&self, function: &F, environment: &E, active_only: bool) -> Vec<(M, Vec<K>)> {
+    let target = ExpansionTarget::Function(function);
+    let mut result: Vec<(M, Vec<K>)> = Vec::new();
+    for (mode, keys, guarded) in self.key_sequences() {
+      let entry = (mode.to_owned(), keys);
+      if result.contains(&entry) {
+        continue;
+      }
+      let found = if active_only {
+        self.is_active(mode, &entry.1, guarded, Some(environment))
+          && self.resolve(&guarded.binding, environment).is_ok_and(|functions| functions.contains(&function))
+      } else {
+        match guarded.binding {
+          Binding::Command(ref command) => self.expands_into::<E>(command, &target, None, &mut Vec::new()),
+          Binding::Function(ref name) => *name == function.to_string(),
+        }
+      };
+      if found {
+        result.push(entry);
+      }
+    }
+    result.sort_by_key(|(_, keys)| keys.len());
+    result
+  }
+
+  /// the binding is the one pressing keys executes, the last one with satisfied when expression
+  fn is_active<E: EnvVariables>(&self, mode: &M, keys: &[K], guarded: &GuardedBinding, environment: Option<&E>) -> bool {
+    let environment = match environment {
+      Some(environment) => environment,
+      None => return true,
+    };
+    self.tree.get(mode)
+      .and_then(|node| node.evaluate(keys).ok())
+      .and_then(|node| self.active_binding(node, environment).ok().flatten())
+      .is_some_and(|active| std::ptr::eq(active, &guarded.binding))
+  }
+
+  /// walks the command expansion graph like command execution does. With an environment, commands with unsatisfied
+  /// when expressions are not expanded and groups only expand into the command their dispatch selects.
+  fn expands_into<'a, E: EnvVariables>(&'a self, name: &'a str, target: &ExpansionTarget<F>, environment: Option<&E>, visited: &mut Vec<&'a str>) -> bool {
+    if visited.contains(&name) {
+      return false;
+    }
+    visited.push(name);
+    let command = match self.commands.get(name) {
+      Some(command) => command,
+      None => return false,
+    };
+    if let Some(environment) = environment {
+      if !command.condition().is_satisfied(environment, self.when_fallback).unwrap_or(false) {
+        return false;
+      }
+    }
+    let found = match target {
+      ExpansionTarget::Command(target) => name == *target,
+      ExpansionTarget::Function(target) => command.functions().any(|f| f == *target),
+    };
+    found || command.expanded_command_names(&self.commands, environment, self.when_fallback).into_iter()
+      .any(|next| self.expands_into(next, target, environment, visited))
+  }
+
+  /// every bound key sequence of every mode, with each of its bindings
+  pub(crate) fn key_sequences(&self) -> Vec<(&M, Vec<K>, &GuardedBinding)> {
+    let mut result = Vec::new();
+    for (mode, node) in &self.tree {
+      collect_key_sequences(node, &mut Vec::new(), &mut |keys, guarded| result.push((mode, keys.to_vec(), guarded)));
+    }
+    result
+  }
+}
+
+enum ExpansionTarget<'a, F: Function> {
+  Command(&'a str),
+  Function(&'a F),
+}
+
+fn collect_key_sequences<'a, K: Key>(node: &'a KeyMapNode<K>, keys: &mut Vec<K>, add: &mut impl FnMut(&[K], &'a GuardedBinding)) {
+  for guarded in &node.bindings {
+    add(keys, guarded);
+  }
+  for (key, next) in node.next.iter().flatten() {
+    keys.push(key.to_owned());
+    collect_key_sequences(next, keys, add);
+    keys.pop();
+  }
+}
+
+#[test]
+fn command_infos_test() {
+  use crate::types::{FunctionString, KeyCode, Mode};
+  use super::test_tree;
+
+  let (tree, env) = test_tree(r#"{
+    "commands": [
+      {"name": "save", "commands": ["function_one"], "title": "Save"},
+      {"name": "unbound", "commands": ["function_two"]}
+    ],
+    "key_maps": [
+      {"keys": ["s"], "command": "save"},
+      {"keys": ["<c-x>", "s"], "command": "save", "mode": ["Normal", "Insert"]},
+      {"keys": ["f"], "command": "function_one"}
+    ]
+  }"#, &["function_one", "function_two"]);
+
+  let infos = tree.command_infos();
+  assert_eq!(infos.iter().map(|i| i.name.as_str()).collect::<Vec<_>>(), vec!["save", "unbound"]);
+  assert_eq!(infos[0].metadata.title.as_deref(), Some("Save"));
+  let mut normal = infos[0].bindings[&Mode::from("Normal")].clone();
+  normal.sort_by_key(|keys| keys.len());
+  assert_eq!(normal, vec![vec![KeyCode::from("s")], vec![KeyCode::from("<c-x>"), KeyCode::from("s")]]);
+  assert_eq!(infos[0].bindings[&Mode::from("Insert")], vec![vec![KeyCode::from("<c-x>"), KeyCode::from("s")]]);
+  assert!(infos[1].bindings.is_empty());
+
+  assert_eq!(tree.execute_command("unbound", &env), Ok(vec![&FunctionString::from("function_two")]));
+  assert_eq!(tree.execute_command("missing", &env), Err(KeyMapError::CommandNotFound("missing".to_owned())));
+}
+
+#[test]
+fn bindings_for_test() {
+  use crate::types::{FunctionString, KeyCode, Mode};
+  use super::test_tree;
+
+  let (tree, mut env) = test_tree(r#"{
+    "commands": [
+      {"name": "save", "commands": ["function_one"]},
+      {"name": "save_all", "commands": ["save", "function_two"]},
+      {"name": "hidden", "commands": ["save"], "when": "false"},
+      {"name": "first", "commands": ["hidden", "save", "save_all"], "command_type": "CommandGroup", "dispatch": "First"}
+    ],
+    "key_maps": [
+      {"keys": ["s"], "command": "save"},
+      {"keys": ["a", "s"], "command": "save_all"},
+      {"keys": ["h"], "command": "hidden"},
+      {"keys": ["x"], "command": "save", "when": "false"},
+      {"keys": ["f"], "command": "function_one", "mode": ["Insert"]},
+      {"keys": ["d"], "command": "save"},
+      {"keys": ["d"], "command": "function_two", "when": "flag"},
+      {"keys": ["p"], "command": "first", "mode": ["Visual"]}
+    ]
+  }"#, &["function_one", "function_two"]);
+  env.set_environment_var("flag".to_owned(), logical_expr::ContextValue::Bool(true));
+  let normal = Mode::from("Normal");
+
+  let mut all = tree.bindings_for_command("save", &normal, &env, false);
+  all.sort_by_key(|keys| format!("{keys:?}"));
+  assert_eq!(all, vec![
+    vec![KeyCode::from("a"), KeyCode::from("s")],
+    vec![KeyCode::from("d")],
+    vec![KeyCode::from("h")],
+    vec![KeyCode::from("s")],
+    vec![KeyCode::from("x")],
+  ]);
+  let mut active = tree.bindings_for_command("save", &normal, &env, true);
+  active.sort_by_key(|keys| format!("{keys:?}"));
+  // d executes function_two, the later binding overrides save
+  assert_eq!(active, vec![vec![KeyCode::from("a"), KeyCode::from("s")], vec![KeyCode::from("s")]]);
+
+  // first never selects save_all, save is unconditional and listed before it
+  let visual = Mode::from("Visual");
+  assert_eq!(tree.bindings_for_command("save", &visual, &env, false), vec![vec![KeyCode::from("p")]]);
+  assert_eq!(tree.bindings_for_command("save", &visual, &env, true), vec![vec![KeyCode::from("p")]]);
+  assert!(tree.bindings_for_command("save_all", &visual, &env, false).is_empty());
+  assert!(tree.bindings_for_command("save_all", &visual, &env, true).is_empty());
+
+  let function_one = FunctionString::from("function_one");
+  assert_eq!(tree.bindings_for_function(&function_one, &env, false).len(), 7);
+  let mut active = tree.bindings_for_function(&function_one, &env, true);
+  active.sort_by_key(|entry| format!("{entry:?}"));
+  assert_eq!(active, vec![
+    (Mode::from("Insert"), vec![KeyCode::from("f")]),
+    (normal.clone(), vec![KeyCode::from("a"), KeyCode::from("s")]),
+    (normal.clone(), vec![KeyCode::from("s")]),
+    (visual.clone(), vec![KeyCode::from("p")]),
+  ]);
+  let mut active = tree.bindings_for_function(&FunctionString::from("function_two"), &env, true);
+  active.sort_by_key(|entry| format!("{entry:?}"));
+  assert_eq!(active, vec![(normal.clone(), vec![KeyCode::from("a"), KeyCode::from("s")]), (normal, vec![KeyCode::from("d")])]);
+  assert!(!tree.bindings_for_function(&FunctionString::from("function_two"), &env, false).contains(&(visual, vec![KeyCode::from("p")])));
+}
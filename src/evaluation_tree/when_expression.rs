@@ -1,22 +1,370 @@
 use crate::environment::{EnvVariables};
+use crate::KeyMapError;
 use logical_expr::evaluate;
 
-#[derive(Debug)]
+/// what to do if a when expression can not be evaluated at key press time, e.g. because it references an undefined variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WhenFallback {
+  /// return a KeyMapError::WhenExpression
+  #[default]
+  Error,
+  /// treat the condition as satisfied
+  True,
+  /// treat the condition as not satisfied
+  False,
+}
+
+// the when expression is parsed while building the tree, such that malformed expressions fail on init.
+// logical_expr only evaluates expression strings, therefore && || ! and parentheses are kept as tree and
+// only the atoms, a context variable or a comparison like `language == "rust"`, are passed to logical_expr.
+//
+// The supported grammar, from lowest to highest precedence. An empty expression is "true".
+//   or         := and ('||' and)*
+//   and        := comparison ('&&' comparison)*
+//   comparison := unary (('==' | '!=' | '<=' | '>=' | '<' | '>') unary)?
+//   unary      := '!' unary | '(' or ')' | operand
+//   operand    := true | false | number | variable | "string" | 'string'
+// Like in C, ! binds tighter than comparisons, "!a == b" is "(!a) == b". Comparisons of two operands are atoms,
+// comparisons with a negated or parenthesized side only support == and != and compare the two truth values.
+#[derive(Debug, Clone, PartialEq)]
+enum ParsedWhen {
+  Constant(bool),
+  Atom(String),
+  Not(Box<ParsedWhen>),
+  And(Vec<ParsedWhen>),
+  Or(Vec<ParsedWhen>),
+  Equal(Box<ParsedWhen>, Box<ParsedWhen>),
+}
+
+#[derive(Debug, Clone)]
 pub struct Condition {
-  when: String,
+  when: ParsedWhen,
+  expression: String, // as written, for error messages
+}
+
+// conditions are equal if they parse to the same expression, e.g. "a&&b" and "a && b" or "b && a"
+impl PartialEq for Condition {
+  fn eq(&self, other: &Self) -> bool {
+    self.when.normalized() == other.when.normalized()
+  }
 }
 
 impl Default for Condition {
   fn default() -> Self {
-    Self { when: "true".to_owned() }
+    Self { when: ParsedWhen::Constant(true), expression: "true".to_owned() }
   }
 }
 
 impl Condition {
-  pub fn new(when: &str) -> Self {
-    Self { when: when.to_owned() }
+  pub fn new(when: &str) -> Result<Self, KeyMapError> {
+    let expression = when.trim();
+    let when = parse(expression).map_err(|message| KeyMapError::WhenExpression { expression: expression.to_owned(), message })?;
+    Ok(Self { when, expression: expression.to_owned() })
+  }
+
+  /// the condition is satisfied in every environment, e.g. "true" or an omitted when expression
+  pub(crate) fn is_always_true(&self) -> bool {
+    self.when == ParsedWhen::Constant(true)
+  }
+
+  /// the fallback applies to the whole condition, if any variable or comparison can not be evaluated
+  pub fn is_satisfied<E: EnvVariables>(&self, environment: &E, fallback: WhenFallback) -> Result<bool, KeyMapError> {
+    match evaluate_parsed(&self.when, environment) {
+      Ok(value) => Ok(value),
+      Err(message) => match fallback {
+        WhenFallback::Error => Err(KeyMapError::WhenExpression { expression: self.expression.clone(), message }),
+        WhenFallback::True => Ok(true),
+        WhenFallback::False => Ok(false),
+      },
+    }
+  }
+}
+
+impl ParsedWhen {
+  // the operands of && and || in a fixed order, only used for comparisons since evaluation keeps the written order
+  fn normalized(&self) -> ParsedWhen {
+    let sorted = |operands: &[ParsedWhen]| {
+      let mut operands: Vec<ParsedWhen> = operands.iter().map(|o| o.normalized()).collect();
+      operands.sort_by_key(|o| format!("{o:?}"));
+      operands
+    };
+    match self {
+      ParsedWhen::Not(inner) => ParsedWhen::Not(Box::new(inner.normalized())),
+      ParsedWhen::And(operands) => ParsedWhen::And(sorted(operands)),
+      ParsedWhen::Or(operands) => ParsedWhen::Or(sorted(operands)),
+      ParsedWhen::Equal(left, right) => {
+        let mut sides = sorted(&[*left.clone(), *right.clone()]);
+        let right = sides.pop().expect("two sides");
+        ParsedWhen::Equal(Box::new(sides.pop().expect("two sides")), Box::new(right))
+      }
+      when => when.clone(),
+    }
+  }
+}
+
+// && and || short circuit, like in the expression string
+fn evaluate_parsed<E: EnvVariables>(when: &ParsedWhen, environment: &E) -> Result<bool, String> {
+  match when {
+    ParsedWhen::Constant(value) => Ok(*value),
+    ParsedWhen::Not(inner) => Ok(!evaluate_parsed(inner, environment)?),
+    ParsedWhen::And(operands) => {
+      for operand in operands {
+        if !evaluate_parsed(operand, environment)? {
+          return Ok(false);
+        }
+      }
+      Ok(true)
+    }
+    ParsedWhen::Or(operands) => {
+      for operand in operands {
+        if evaluate_parsed(operand, environment)? {
+          return Ok(true);
+        }
+      }
+      Ok(false)
+    }
+    ParsedWhen::Equal(left, right) => Ok(evaluate_parsed(left, environment)? == evaluate_parsed(right, environment)?),
+    ParsedWhen::Atom(atom) => evaluate(atom, environment.environment_variables()).map_err(|e| format!("{atom}: {e:?}")),
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Open,
+  Close,
+  And,
+  Or,
+  Not,
+  Compare(&'static str),
+  Operand(String), // a variable, number, true, false or a quoted string
+}
+
+const COMPARISONS: [&str; 6] = ["==", "!=", "<=", ">=", "<", ">"];
+
+fn tokenize(when: &str) -> Result<Vec<Token>, String> {
+  let mut tokens = Vec::new();
+  let mut rest = when.trim_start();
+  while let Some(c) = rest.chars().next() {
+    let position = when.len() - rest.len();
+    let (token, length) = if let Some(op) = COMPARISONS.iter().find(|op| rest.starts_with(**op)) {
+      (Token::Compare(op), op.len())
+    } else if rest.starts_with("&&") {
+      (Token::And, 2)
+    } else if rest.starts_with("||") {
+      (Token::Or, 2)
+    } else {
+      match c {
+        '(' => (Token::Open, 1),
+        ')' => (Token::Close, 1),
+        '!' => (Token::Not, 1),
+        '"' | '\'' => {
+          let end = rest[1..].find(c).ok_or("unterminated string".to_owned())? + 2;
+          (Token::Operand(rest[..end].to_owned()), end)
+        }
+        c if c.is_alphanumeric() || "_.-".contains(c) => {
+          let end = rest.find(|c: char| !(c.is_alphanumeric() || "_.-".contains(c))).unwrap_or(rest.len());
+          (Token::Operand(rest[..end].to_owned()), end)
+        }
+        c => return Err(format!("unexpected '{c}' at position {position}")),
+      }
+    };
+    tokens.push(token);
+    rest = rest[length..].trim_start();
+  }
+  Ok(tokens)
+}
+
+fn parse(when: &str) -> Result<ParsedWhen, String> {
+  let tokens = tokenize(when)?;
+  if tokens.is_empty() {
+    return Ok(ParsedWhen::Constant(true));
+  }
+  let mut parser = Parser { tokens, position: 0 };
+  let parsed = parser.or()?;
+  match parser.peek() {
+    None => Ok(parsed),
+    Some(Token::Close) => Err("unmatched ')'".to_owned()),
+    Some(token) => Err(format!("unexpected {} after complete expression", describe(token))),
+  }
+}
+
+fn describe(token: &Token) -> String {
+  match token {
+    Token::Open => "'('".to_owned(),
+    Token::Close => "')'".to_owned(),
+    Token::And => "'&&'".to_owned(),
+    Token::Or => "'||'".to_owned(),
+    Token::Not => "'!'".to_owned(),
+    Token::Compare(op) => format!("'{op}'"),
+    Token::Operand(operand) => format!("'{operand}'"),
+  }
+}
+
+// an operand is kept as written until it is clear whether it is part of a comparison
+enum Term {
+  Operand(String),
+  When(ParsedWhen),
+}
+
+impl Term {
+  fn into_when(self) -> ParsedWhen {
+    match self {
+      Term::Operand(operand) => match operand.as_str() {
+        "true" => ParsedWhen::Constant(true),
+        "false" => ParsedWhen::Constant(false),
+        _ => ParsedWhen::Atom(operand),
+      },
+      Term::When(when) => when,
+    }
+  }
+}
+
+// one function per rule of the grammar above
+struct Parser {
+  tokens: Vec<Token>,
+  position: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.position)
+  }
+  fn next(&mut self) -> Result<Token, String> {
+    let token = self.tokens.get(self.position).cloned().ok_or("unexpected end of expression".to_owned())?;
+    self.position += 1;
+    Ok(token)
+  }
+
+  fn or(&mut self) -> Result<ParsedWhen, String> {
+    let mut operands = vec![self.and()?];
+    while self.peek() == Some(&Token::Or) {
+      self.position += 1;
+      operands.push(self.and()?);
+    }
+    Ok(fold(operands, false))
+  }
+
+  fn and(&mut self) -> Result<ParsedWhen, String> {
+    let mut operands = vec![self.comparison()?];
+    while self.peek() == Some(&Token::And) {
+      self.position += 1;
+      operands.push(self.comparison()?);
+    }
+    Ok(fold(operands, true))
+  }
+
+  fn comparison(&mut self) -> Result<ParsedWhen, String> {
+    let left = self.unary()?;
+    let op = match self.peek() {
+      Some(Token::Compare(op)) => *op,
+      _ => return Ok(left.into_when()),
+    };
+    self.position += 1;
+    if self.peek().is_none() {
+      return Err(format!("expected a value after '{op}'"));
+    }
+    match (left, self.unary()?) {
+      (Term::Operand(left), Term::Operand(right)) => Ok(ParsedWhen::Atom(format!("{left} {op} {right}"))),
+      (left, right) if op == "==" || op == "!=" => {
+        let equal = match (left.into_when(), right.into_when()) {
+          (ParsedWhen::Constant(left), ParsedWhen::Constant(right)) => ParsedWhen::Constant(left == right),
+          (left, right) => ParsedWhen::Equal(Box::new(left), Box::new(right)),
+        };
+        Ok(if op == "==" { equal } else { negate(equal) })
+      }
+      _ => Err(format!("'{op}' compares two values, negated or parenthesized expressions only support == and !=")),
+    }
+  }
+
+  fn unary(&mut self) -> Result<Term, String> {
+    match self.next()? {
+      Token::Not => Ok(Term::When(negate(self.unary()?.into_when()))),
+      Token::Open => {
+        let inner = self.or()?;
+        match self.next() {
+          Ok(Token::Close) => Ok(Term::When(inner)),
+          _ => Err("unclosed '('".to_owned()),
+        }
+      }
+      Token::Operand(operand) => Ok(Term::Operand(operand)),
+      token => Err(format!("unexpected {}", describe(&token))),
+    }
+  }
+}
+
+fn negate(when: ParsedWhen) -> ParsedWhen {
+  match when {
+    ParsedWhen::Constant(value) => ParsedWhen::Constant(!value),
+    when => ParsedWhen::Not(Box::new(when)),
+  }
+}
+
+// folds constants of && (is_and) or || operands, e.g. "true && a" is a
+fn fold(operands: Vec<ParsedWhen>, is_and: bool) -> ParsedWhen {
+  let mut remaining = Vec::new();
+  for operand in operands {
+    match operand {
+      ParsedWhen::Constant(value) if value == is_and => {},
+      ParsedWhen::Constant(value) => return ParsedWhen::Constant(value),
+      operand => remaining.push(operand),
+    }
+  }
+  match remaining.len() {
+    0 => ParsedWhen::Constant(is_and),
+    1 => remaining.remove(0),
+    _ if is_and => ParsedWhen::And(remaining),
+    _ => ParsedWhen::Or(remaining),
   }
-  pub fn is_satisfied<E: EnvVariables>(&self, environment: &E) -> bool {
-    evaluate(&self.when, environment.environment_variables()).unwrap()
+}
+
+#[test]
+fn condition_new_test() {
+  assert_eq!(Condition::new(" true "), Ok(Condition::default()));
+  assert_eq!(Condition::new("!(false || false) && true"), Ok(Condition::default()));
+  assert_eq!(Condition::new("a&&b"), Condition::new("(a && b)"));
+  assert_eq!(Condition::new("a && (b || c)"), Condition::new("(c || b) && a"));
+  assert_ne!(Condition::new("a && b"), Condition::new("a || b"));
+  assert!(Condition::new("\"(\" == a || !b").is_ok());
+  assert_eq!(Condition::new(""), Ok(Condition::default()));
+  assert_eq!(Condition::new("!a == b"), Condition::new("(!a) == b"));
+  assert_ne!(Condition::new("!a == b"), Condition::new("!(a == b)"));
+  for malformed in ["((", "a)", "a &&", "&& b", "a b", "a ==", "a == && b", "'a", "a # b", "!a < b"] {
+    assert!(matches!(Condition::new(malformed), Err(KeyMapError::WhenExpression { .. })), "{malformed}");
+  }
+}
+
+// expressions which were passed to logical_expr as a whole before they were parsed
+#[test]
+fn condition_compatibility_test() {
+  use logical_expr::ContextValue;
+  let mut env = crate::environment::DefaultEnvironment::new();
+  env.set_environment_var("yes".to_owned(), ContextValue::Bool(true));
+  env.set_environment_var("no".to_owned(), ContextValue::Bool(false));
+  for (expression, expected) in [
+    ("yes", true),
+    ("!no", true),
+    ("!!yes", true),
+    ("yes && no", false),
+    ("yes || no", true),
+    ("no || yes && no", false),
+    ("(no || yes) && !no", true),
+    ("!yes == no", true),
+    ("(yes && no) != no", false),
+    ("  true  ", true),
+  ] {
+    let condition = Condition::new(expression).unwrap();
+    assert_eq!(condition.is_satisfied(&env, WhenFallback::Error), Ok(expected), "{expression}");
   }
-}
\ No newline at end of file
+}
+
+#[test]
+fn condition_fallback_test() {
+  let env = crate::environment::DefaultEnvironment::new();
+  let condition = Condition::new("undefined_variable").unwrap();
+  assert!(matches!(condition.is_satisfied(&env, WhenFallback::Error), Err(KeyMapError::WhenExpression { .. })));
+  assert_eq!(condition.is_satisfied(&env, WhenFallback::True), Ok(true));
+  assert_eq!(condition.is_satisfied(&env, WhenFallback::False), Ok(false));
+  assert_eq!(Condition::new("false").unwrap().is_satisfied(&env, WhenFallback::True), Ok(false));
+  // the fallback applies to the whole condition, not to the undefined variable
+  assert_eq!(Condition::new("!undefined_variable").unwrap().is_satisfied(&env, WhenFallback::True), Ok(true));
+}
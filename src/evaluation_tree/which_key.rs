@@ -0,0 +1,110 @@
+use crate::environment::EnvVariables;
+use crate::json_parser::Metadata;
+use crate::{Function, Key, KeyMapError};
+
+use super::{Binding, EvaluationTree, KeyMapNode};
+
+/// a key sequence in the which key subtree, see KeyParser::which_key
+#[derive(Clone, Debug, PartialEq)]
+pub struct WhichKeyEntry<K: Key> {
+  /// the full key sequence including the prefix
+  pub keys: Vec<K>,
+  /// the bindings of exactly this key sequence
+  pub bindings: Vec<WhichKeyBinding>,
+  /// the continuations of this key sequence
+  pub children: Vec<WhichKeyEntry<K>>,
+}
+
+impl<K: Key> WhichKeyEntry<K> {
+  /// the last key of the sequence
+  pub fn key(&self) -> &K {
+    self.keys.last().expect("a which key entry has at least one key")
+  }
+  pub fn is_prefix(&self) -> bool {
+    !self.children.is_empty()
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WhichKeyBinding {
+  pub binding: Binding,
+  /// the binding is executed by its keys in the current environment. Its when expression is satisfied
+  /// and no later binding of the same keys with satisfied when expression overrides it
+  pub is_active: bool,
+  /// the metadata of the key map, missing fields are taken from the bound command
+  pub metadata: Metadata,
+  /// the configuration layer the key map originates from
+  pub layer: String,
+}
+
+impl<M: Key, K: Key, F: Function> EvaluationTree<M, K, F> {
+  /// the subtree of bindings below prefix. Bindings which resolve to no functions are left out,
+  /// as are continuations without any binding left.
+  pub(crate) fn which_key<E: EnvVariables>(&self, mode: &M, prefix: &[K], environment: &E) -> Result<Vec<WhichKeyEntry<K>>, KeyMapError> {
+    let node = self.tree.get(mode).ok_or(KeyMapError::unknown_mode(mode))?.evaluate(prefix)?;
+    Ok(self.which_key_children(node, prefix, environment))
+  }
+
+  fn which_key_children<E: EnvVariables>(&self, node: &KeyMapNode<K>, keys: &[K], environment: &E) -> Vec<WhichKeyEntry<K>> {
+    node.next.iter().flatten().filter_map(|(key, next)| {
+      let mut keys = keys.to_vec();
+      keys.push(key.to_owned());
+      let active = self.active_binding(next, environment).ok().flatten();
+      let bindings: Vec<WhichKeyBinding> = next.bindings.iter()
+        .filter(|guarded| self.resolve(&guarded.binding, environment).is_ok_and(|functions| !functions.is_empty()))
+        .map(|guarded| WhichKeyBinding {
+          binding: guarded.binding.to_owned(),
+          is_active: active.is_some_and(|active| std::ptr::eq(active, &guarded.binding)),
+          metadata: match guarded.binding {
+            Binding::Command(ref name) => guarded.metadata.or(self.command_metadata(name).unwrap_or(&Metadata::default())),
+            Binding::Function(_) => guarded.metadata.clone(),
+          },
+          layer: guarded.layer.clone(),
+        })
+        .collect();
+      let children = self.which_key_children(next, &keys, environment);
+      if bindings.is_empty() && children.is_empty() {
+        None
+      } else {
+        Some(WhichKeyEntry { keys, bindings, children })
+      }
+    }).collect()
+  }
+}
+
+#[test]
+fn which_key_test() {
+  use crate::types::{KeyCode, Mode};
+  use super::test_tree;
+
+  let (tree, mut env) = test_tree(r#"{
+    "commands": [
+      {"name": "save", "commands": ["function_one"]},
+      {"name": "never", "commands": ["function_two"], "when": "false"}
+    ],
+    "key_maps": [
+      {"keys": ["g"], "command": "save"},
+      {"keys": ["g", "g"], "command": "save", "when": "false"},
+      {"keys": ["g", "x"], "command": "never"},
+      {"keys": ["x"], "command": "never"},
+      {"keys": ["y"], "command": "save"},
+      {"keys": ["y"], "command": "function_two", "when": "flag"}
+    ]
+  }"#, &["function_one", "function_two"]);
+  env.set_environment_var("flag".to_owned(), logical_expr::ContextValue::Bool(true));
+
+  let mut entries = tree.which_key(&Mode::from("Normal"), &[], &env).unwrap();
+  entries.sort_by_key(|entry| format!("{:?}", entry.keys));
+  assert_eq!(entries.len(), 2); // x only leads to never, which has no functions
+  // both when expressions of y are satisfied, the later binding overrides the earlier one
+  assert_eq!(entries[1].bindings.iter().map(|b| (b.binding.name(), b.is_active)).collect::<Vec<_>>(), vec![("save", false), ("function_two", true)]);
+  assert_eq!(entries[0].key(), &KeyCode::from("g"));
+  assert!(entries[0].is_prefix());
+  assert_eq!(entries[0].children, vec![WhichKeyEntry {
+    keys: vec![KeyCode::from("g"), KeyCode::from("g")],
+    bindings: vec![WhichKeyBinding { binding: Binding::Command("save".to_owned()), is_active: false, metadata: Metadata::default(), layer: String::new() }],
+    children: vec![],
+  }]);
+  assert_eq!(tree.which_key(&Mode::from("Normal"), &[KeyCode::from("g")], &env).unwrap(), entries[0].children);
+  assert!(tree.which_key(&Mode::from("Insert"), &[], &env).is_err());
+}
@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+use crate::evaluation_tree::when_expression::Condition;
+use crate::json_parser::{KeyMap, KeyMapData, Metadata};
+use crate::{Key, KeyMapError};
+
+/// the output format of KeyParser::cheat_sheet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatSheetFormat {
+  Markdown,
+  Html,
+}
+
+/// drops the modes of key maps which are overridden by a later key map with the same keys and when expression,
+/// like the evaluation tree does: keys are compared as K and when expressions as parsed conditions, e.g. "a && b"
+/// overrides "b && a". Malformed when expressions are compared as written. Expects removals to be applied already.
+pub(crate) fn effective_key_map_data<K: Key>(mut data: KeyMapData) -> KeyMapData {
+  let parse = |key_map: &KeyMap| (
+    key_map.keys.iter().map(|k| K::from(k.to_owned())).collect::<Vec<K>>(),
+    Condition::new(&key_map.when).map_err(|_| key_map.when.trim().to_owned()),
+  );
+  let mut key_maps: Vec<(KeyMap, _)> = Vec::new();
+  for key_map in data.key_maps.drain(..) {
+    let parsed = parse(&key_map);
+    key_maps.iter_mut()
+      .filter(|(_, other)| *other == parsed)
+      .for_each(|(k, _)| k.mode.retain(|m| !key_map.mode.contains(m)));
+    key_maps.retain(|(k, _)| !k.mode.is_empty());
+    key_maps.push((key_map, parsed));
+  }
+  data.key_maps = key_maps.into_iter().map(|(key_map, _)| key_map).collect();
+  data
+}
+
+pub(crate) fn to_json(data: &KeyMapData) -> Result<String, KeyMapError> {
+  serde_json::to_string_pretty(data).map_err(|e| KeyMapError::Serialize(e.to_string()))
+}
+
+struct Row {
+  keys: String,
+  command: String,
+  title: String,
+  description: String,
+}
+
+// mode -> category -> rows, sorted by name. Bindings without category are listed under "Other"
+fn rows(data: &KeyMapData) -> BTreeMap<&str, BTreeMap<String, Vec<Row>>> {
+  let mut modes: BTreeMap<&str, BTreeMap<String, Vec<Row>>> = BTreeMap::new();
+  for key_map in &data.key_maps {
+    let command_metadata = data.commands.iter().find(|c| c.name == key_map.command).map(|c| &c.metadata);
+    let metadata = key_map.metadata.or(command_metadata.unwrap_or(&Metadata::default()));
+    for mode in &key_map.mode {
+      modes.entry(mode).or_default()
+        .entry(metadata.category.clone().unwrap_or_else(|| "Other".to_owned())).or_default()
+        .push(Row {
+          keys: key_map.keys.join(" "),
+          command: key_map.command.clone(),
+          title: metadata.title.clone().unwrap_or_else(|| key_map.command.clone()),
+          description: metadata.description.clone().unwrap_or_default(),
+        });
+    }
+  }
+  modes.values_mut().flat_map(|categories| categories.values_mut()).for_each(|rows| rows.sort_by(|a, b| a.keys.cmp(&b.keys)));
+  modes
+}
+
+pub(crate) fn cheat_sheet(data: &KeyMapData, format: CheatSheetFormat) -> String {
+  match format {
+    CheatSheetFormat::Markdown => markdown(data),
+    CheatSheetFormat::Html => html(data),
+  }
+}
+
+// keys go into a code span fenced by more backticks than they contain, so only the table's | needs escaping
+fn markdown_code(text: &str) -> String {
+  let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+  let fence = "`".repeat(longest_run + 1);
+  let padding = if text.starts_with('`') || text.ends_with('`') { " " } else { "" };
+  format!("{fence}{padding}{}{padding}{fence}", text.replace('|', "\\|"))
+}
+
+fn markdown_text(text: &str) -> String {
+  text.chars().fold(String::new(), |mut result, c| {
+    if matches!(c, '\\' | '`' | '*' | '_' | '<' | '>' | '|' | '[' | ']') {
+      result.push('\\');
+    }
+    result.push(c);
+    result
+  })
+}
+
+fn markdown(data: &KeyMapData) -> String {
+  let mut result = String::from("# Key Bindings\n");
+  for (mode, categories) in rows(data) {
+    result.push_str(&format!("\n## {mode}\n"));
+    for (category, rows) in categories {
+      result.push_str(&format!("\n### {category}\n\n| Keys | Command | Description |\n| --- | --- | --- |\n"));
+      for row in rows {
+        result.push_str(&format!("| {} | {} | {} |\n", markdown_code(&row.keys), markdown_text(&row.title), markdown_text(&row.description)));
+      }
+    }
+  }
+  result
+}
+
+fn html(data: &KeyMapData) -> String {
+  let escape = |text: &str| text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+  let mut result = String::from("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Key Bindings</title></head>\n<body>\n<h1>Key Bindings</h1>\n");
+  for (mode, categories) in rows(data) {
+    result.push_str(&format!("<h2>{}</h2>\n", escape(mode)));
+    for (category, rows) in categories {
+      result.push_str(&format!("<h3>{}</h3>\n<table>\n<tr><th>Keys</th><th>Command</th><th>Description</th></tr>\n", escape(&category)));
+      for row in rows {
+        result.push_str(&format!("<tr><td><kbd>{}</kbd></td><td title=\"{}\">{}</td><td>{}</td></tr>\n",
+          escape(&row.keys), escape(&row.command), escape(&row.title), escape(&row.description)));
+      }
+      result.push_str("</table>\n");
+    }
+  }
+  result.push_str("</body>\n</html>\n");
+  result
+}
+
+#[test]
+fn cheat_sheet_test() {
+  use crate::types::KeyCode;
+  let data: KeyMapData = serde_json::from_str(r#"{
+    "commands": [{"name": "save", "commands": ["write"], "title": "Save", "category": "File"}],
+    "key_maps": [
+      {"keys": ["<c-s>"], "command": "save", "mode": ["Normal", "Insert"]},
+      {"keys": ["q"], "command": "quit", "description": "close a | b"},
+      {"keys": ["q"], "command": "quit_all", "mode": ["Insert"]}
+    ]
+  }"#).unwrap();
+  let data = effective_key_map_data::<KeyCode>(data);
+  assert_eq!(data.key_maps.len(), 3);
+
+  let markdown = cheat_sheet(&data, CheatSheetFormat::Markdown);
+  assert!(markdown.contains("## Insert\n\n### File\n"));
+  assert!(markdown.contains("| `<c-s>` | Save |  |\n"));
+  assert!(markdown.contains("| `q` | quit | close a \\| b |\n"));
+  assert!(markdown.find("## Insert").unwrap() < markdown.find("## Normal").unwrap());
+
+  let html = cheat_sheet(&data, CheatSheetFormat::Html);
+  assert!(html.contains("<tr><td><kbd>&lt;c-s&gt;</kbd></td><td title=\"save\">Save</td><td></td></tr>"));
+  assert_eq!(html.matches("<table>").count(), 4);
+}
+
+#[test]
+fn effective_key_map_data_test() {
+  use crate::types::KeyPress;
+  let data: KeyMapData = serde_json::from_str(r#"{"key_maps": [
+    {"keys": ["a"], "command": "one", "mode": ["Normal", "Insert"]},
+    {"keys": ["a"], "command": "two", "when": " true"},
+    {"keys": ["a"], "command": "three", "when": "x"},
+    {"keys": ["<C-W>"], "command": "four", "when": "x || y"},
+    {"keys": ["<c-w>"], "command": "five", "when": "y || x"}
+  ]}"#).unwrap();
+  let data = effective_key_map_data::<KeyPress>(data);
+  let commands: Vec<(&str, &Vec<String>)> = data.key_maps.iter().map(|k| (k.command.as_str(), &k.mode)).collect();
+  assert_eq!(commands, vec![
+    ("one", &vec!["Insert".to_owned()]),
+    ("two", &vec!["Normal".to_owned()]),
+    ("three", &vec!["Normal".to_owned()]),
+    ("five", &vec!["Normal".to_owned()]),
+  ]);
+}
+
+#[test]
+fn markdown_escape_test() {
+  let data: KeyMapData = serde_json::from_str(r#"{"key_maps": [
+    {"keys": ["`", "a"], "command": "tick", "description": "*bold* _x_ <b> [a](b) `c`"},
+    {"keys": ["|", "*_<lt>"], "command": "pipe"}
+  ]}"#).unwrap();
+  let markdown = cheat_sheet(&data, CheatSheetFormat::Markdown);
+  assert!(markdown.contains("| `` ` a `` | tick | \\*bold\\* \\_x\\_ \\<b\\> \\[a\\](b) \\`c\\` |\n"));
+  assert!(markdown.contains("| `\\| *_<lt>` | pipe |  |\n"));
+}
@@ -0,0 +1,31 @@
+//! converts key bindings of other programs into KeyMapData, which can be used with KeyParser::from_data
+//! or serialized into a key map json file.
+pub mod vim;
+pub mod vscode;
+
+use std::fmt::Display;
+
+use crate::json_parser::KeyMapData;
+
+/// the imported key maps and commands, together with everything which could not be imported
+#[derive(Debug, Default, Clone)]
+pub struct ImportReport {
+  pub data: KeyMapData,
+  pub unsupported: Vec<Unsupported>,
+}
+
+/// an entry which was skipped during import
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsupported {
+  /// 1 based position of the entry, e.g. the n-th binding or the line number
+  pub position: usize,
+  /// the entry as written in the imported file
+  pub entry: String,
+  pub reason: String,
+}
+
+impl Display for Unsupported {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}: {} ({})", self.position, self.reason, self.entry)
+  }
+}
@@ -0,0 +1,247 @@
+use crate::json_parser::{Command, CommandType, KeyMap, KeyMapCommandType};
+use crate::types::split_vim_keys;
+
+use super::{ImportReport, Unsupported};
+
+// the map commands and the modes they bind, noremap variants behave the same since keys are never remapped to keys
+const MAP_COMMANDS: &[(&str, &[&str])] = &[
+  ("map", &["Normal", "Visual", "Select", "OperatorPending"]),
+  ("nmap", &["Normal"]),
+  ("imap", &["Insert"]),
+  ("vmap", &["Visual", "Select"]),
+  ("xmap", &["Visual"]),
+  ("smap", &["Select"]),
+  ("omap", &["OperatorPending"]),
+  ("cmap", &["Command"]),
+];
+
+/// imports the mappings of a vimrc, e.g. "nnoremap <silent> <leader>w :write<CR>".
+/// Ex commands like ":write<CR>" or "<Cmd>write<CR>" and "<Plug>(name)" become functions named "write" and "name",
+/// several of them in one mapping become a command named after the line. mapleader and maplocalleader are respected.
+/// Mappings to plain key sequences, e.g. "nnoremap Y y$", and all other lines are reported as unsupported.
+pub fn from_vimrc(vimrc: &str) -> ImportReport {
+  let mut report = ImportReport::default();
+  let mut leader = "<Bslash>".to_owned();
+  let mut local_leader = "<Bslash>".to_owned();
+  for (index, line) in vimrc.lines().enumerate() {
+    let position = index + 1;
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('"') {
+      continue;
+    }
+    let value = match (let_value(line, "mapleader"), let_value(line, "maplocalleader")) {
+      (Some(value), _) => value.map(|value| leader = value),
+      (_, Some(value)) => value.map(|value| local_leader = value),
+      (None, None) => import_mapping(line, position, &leader, &local_leader).map(|(key_map, command)| {
+        report.data.commands.extend(command);
+        report.data.key_maps.push(key_map);
+      }),
+    };
+    if let Err(reason) = value {
+      report.unsupported.push(Unsupported { position, entry: line.to_owned(), reason });
+    }
+  }
+  report
+}
+
+// the keys of let mapleader = "," as notation, e.g. "," or "<Space>", None if the line does not set the variable.
+// Double quoted strings are decoded like vim does, e.g. "\\" is a backslash and "\<Space>" a space,
+// single quoted strings are literal. Other escapes and values which are no string are an error
+fn let_value(line: &str, variable: &str) -> Option<Result<String, String>> {
+  let (name, value) = line.strip_prefix("let")?.split_once('=')?;
+  if name.trim().trim_start_matches("g:") != variable {
+    return None;
+  }
+  let value = value.trim();
+  let unsupported = || format!("the value of {variable} must be a single string");
+  if let Some(literal) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+    if literal.replace("''", "").contains('\'') {
+      return Some(Err(unsupported()));
+    }
+    return Some(Ok(literal.replace("''", "'").chars().map(key_notation).collect()));
+  }
+  let Some(mut rest) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+    return Some(Err(unsupported()));
+  };
+  let mut keys = String::new();
+  while let Some(c) = rest.chars().next() {
+    rest = &rest[c.len_utf8()..];
+    match c {
+      '"' => return Some(Err(unsupported())),
+      '\\' => match rest.chars().next() {
+        Some(escaped @ ('\\' | '"')) => {
+          keys.push_str(&key_notation(escaped));
+          rest = &rest[1..];
+        }
+        Some('<') => {
+          let Some(end) = rest.find('>') else {
+            return Some(Err(format!("unterminated key code in the value of {variable}")));
+          };
+          keys.push_str(&rest[..=end]);
+          rest = &rest[end + 1..];
+        }
+        _ => return Some(Err(format!("unsupported escape in the value of {variable}"))),
+      },
+      c => keys.push_str(&key_notation(c)),
+    }
+  }
+  Some(Ok(keys))
+}
+
+fn key_notation(c: char) -> String {
+  match c {
+    ' ' => "<Space>".to_owned(),
+    '\\' => "<Bslash>".to_owned(),
+    c => c.to_string(),
+  }
+}
+
+fn import_mapping(line: &str, position: usize, leader: &str, local_leader: &str) -> Result<(KeyMap, Option<Command>), String> {
+  let (map_command, mut rest) = split_word(line);
+  let modes = map_command.strip_suffix("noremap").map(|mode| format!("{mode}map"))
+    .or_else(|| map_command.ends_with("map").then(|| map_command.to_owned()))
+    .and_then(|map| MAP_COMMANDS.iter().find(|(name, _)| *name == map))
+    .map(|(_, modes)| *modes)
+    .ok_or_else(|| format!("{map_command} is not a supported map command"))?;
+
+  let lhs = loop {
+    let (word, tail) = split_word(rest);
+    rest = tail;
+    match word.to_lowercase().as_str() {
+      "<silent>" | "<nowait>" | "<unique>" | "<special>" | "<script>" => continue,
+      "<buffer>" | "<expr>" => return Err(format!("{word} mappings are not supported")),
+      "" => return Err("missing {lhs}".to_owned()),
+      _ => break word,
+    }
+  };
+  if rest.is_empty() {
+    return Err("missing {rhs}".to_owned());
+  }
+  if rest.contains('|') {
+    return Err("| is not supported".to_owned());
+  }
+  let lhs = replace_ignore_case(&replace_ignore_case(lhs, "<localleader>", local_leader), "<leader>", leader);
+  let keys = split_vim_keys(&lhs).map_err(|e| e.to_string())?;
+  if keys.iter().any(|k| k.eq_ignore_ascii_case("<Plug>")) {
+    return Err("<Plug> in {lhs} is not supported".to_owned());
+  }
+
+  let functions = rhs_functions(rest)?;
+  let mut key_map = KeyMap::new(&[], "");
+  key_map.keys = keys;
+  key_map.mode = modes.iter().map(|m| m.to_string()).collect();
+  if functions.len() == 1 {
+    key_map.command = functions[0].to_owned();
+    key_map.command_type = KeyMapCommandType::Function;
+    return Ok((key_map, None));
+  }
+  let command = Command {
+    name: format!("vimrc_line_{position}"),
+    commands: functions,
+    command_type: CommandType::FunctionSequence,
+    ..Command::default()
+  };
+  key_map.command = command.name.clone();
+  key_map.command_type = KeyMapCommandType::Command;
+  Ok((key_map, Some(command)))
+}
+
+// splits a {rhs} like ":write<CR>:quit<CR>" into the functions write and quit
+fn rhs_functions(rhs: &str) -> Result<Vec<String>, String> {
+  let mut functions = Vec::new();
+  let mut rest = rhs.trim();
+  while !rest.is_empty() {
+    if let Some(name) = strip_prefix_ignore_case(rest, "<Plug>") {
+      functions.push(name.trim().trim_start_matches('(').trim_end_matches(')').to_owned());
+      break;
+    }
+    // <C-u> after : clears the range vim inserts for a count or in visual mode
+    let command = strip_prefix_ignore_case(rest, "<Cmd>")
+      .or_else(|| rest.strip_prefix(':').map(|command| strip_prefix_ignore_case(command, "<C-u>").unwrap_or(command)))
+      .ok_or_else(|| format!("{{rhs}} {rhs} is a key sequence, mapping keys to keys is not supported"))?;
+    let end = ["<CR>", "<Enter>", "<Return>"].iter()
+      .filter_map(|cr| find_ignore_case(command, cr).map(|index| (index, cr.len())))
+      .min()
+      .ok_or_else(|| format!("the ex command in {rhs} is not terminated by <CR>"))?;
+    let function = command[..end.0].trim();
+    if function.is_empty() {
+      return Err(format!("empty ex command in {rhs}"));
+    }
+    functions.push(function.to_owned());
+    rest = command[end.0 + end.1..].trim_start();
+  }
+  Ok(functions)
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+  let text = text.trim_start();
+  let end = text.find(char::is_whitespace).unwrap_or(text.len());
+  (&text[..end], text[end..].trim_start())
+}
+
+fn find_ignore_case(text: &str, pattern: &str) -> Option<usize> {
+  text.to_ascii_lowercase().find(&pattern.to_ascii_lowercase())
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+  text.get(..prefix.len()).filter(|start| start.eq_ignore_ascii_case(prefix)).map(|_| &text[prefix.len()..])
+}
+
+fn replace_ignore_case(text: &str, pattern: &str, replacement: &str) -> String {
+  let mut result = String::new();
+  let mut rest = text;
+  while let Some(index) = find_ignore_case(rest, pattern) {
+    result.push_str(&rest[..index]);
+    result.push_str(replacement);
+    rest = &rest[index + pattern.len()..];
+  }
+  result.push_str(rest);
+  result
+}
+
+#[test]
+fn from_vimrc_test() {
+  let report = from_vimrc(r#"
+" comments and empty lines are skipped
+let mapleader = " "
+nnoremap <silent> <leader>w :write<CR>
+nmap <C-s> <Cmd>write<cr>:quit<CR>
+xmap ga <Plug>(EasyAlign)
+inoremap jk <Esc>
+set number
+nnoremap <buffer> x :bd<CR>
+omap <leader>x :a<CR>|:b<CR>
+let maplocalleader = "\\"
+nnoremap <localleader>f :<C-U>call Foo()<CR>
+let mapleader = "\q"
+nmap ctrl+x :cut<CR>
+"#);
+
+  let key_maps = &report.data.key_maps;
+  assert_eq!(key_maps.len(), 5);
+  assert_eq!(key_maps[0].keys, vec!["<Space>", "w"]);
+  assert_eq!(key_maps[0].command, "write");
+  assert!(matches!(key_maps[0].command_type, KeyMapCommandType::Function));
+  assert_eq!(key_maps[1].command, "vimrc_line_5");
+  assert_eq!(report.data.commands[0].commands, vec!["write", "quit"]);
+  assert_eq!(key_maps[2].mode, vec!["Visual"]);
+  assert_eq!(key_maps[2].command, "EasyAlign");
+  assert_eq!(key_maps[3].keys, vec!["<Bslash>", "f"]);
+  assert_eq!(key_maps[3].command, "call Foo()");
+  // vim keys, not a VS Code chord
+  assert_eq!(key_maps[4].keys, vec!["c", "t", "r", "l", "+", "x"]);
+  assert_eq!(report.unsupported.iter().map(|u| u.position).collect::<Vec<_>>(), vec![7, 8, 9, 10, 13]);
+}
+
+#[test]
+fn let_value_test() {
+  assert_eq!(let_value(r#"let mapleader = ",""#, "mapleader"), Some(Ok(",".to_owned())));
+  assert_eq!(let_value(r#"let g:mapleader = "\\""#, "mapleader"), Some(Ok("<Bslash>".to_owned())));
+  assert_eq!(let_value(r#"let mapleader = "\<Space>""#, "mapleader"), Some(Ok("<Space>".to_owned())));
+  assert_eq!(let_value(r#"let mapleader = ' '"#, "mapleader"), Some(Ok("<Space>".to_owned())));
+  assert_eq!(let_value(r#"let mapleader = '\'"#, "mapleader"), Some(Ok("<Bslash>".to_owned())));
+  assert_eq!(let_value(r#"let maplocalleader = ",""#, "mapleader"), None);
+  assert!(matches!(let_value(r#"let mapleader = "\q""#, "mapleader"), Some(Err(_))));
+  assert!(matches!(let_value(r#"let mapleader = "\<Space""#, "mapleader"), Some(Err(_))));
+  assert!(matches!(let_value("let mapleader = g:leader", "mapleader"), Some(Err(_))));
+}
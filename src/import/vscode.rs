@@ -0,0 +1,222 @@
+use serde::Deserialize;
+
+use crate::json_parser::{json_parse_error, Command, CommandType, KeyMap, KeyMapCommandType, MEMORY_SOURCE};
+use crate::types::vscode_chord_to_vim;
+use crate::KeyMapError;
+
+use super::{ImportReport, Unsupported};
+
+#[derive(Deserialize, Debug)]
+struct Keybinding {
+  #[serde(default)]
+  key: String,
+  #[serde(default)]
+  command: String,
+  when: Option<String>,
+  args: Option<serde_json::Value>,
+}
+
+/// imports the content of a VS Code keybindings.json, comments and trailing commas are allowed.
+/// Every bound VS Code command becomes a command with the same name, running the function of that name, bound in Normal mode.
+/// Removals like "-editor.action.commentLine" unbind the key from the command, their when clause is ignored.
+/// Bindings with args or a when clause which can not be translated are reported as unsupported.
+pub fn from_keybindings(json: &str) -> Result<ImportReport, KeyMapError> {
+  let keybindings: Vec<serde_json::Value> = serde_json::from_str(&strip_jsonc(json)).map_err(|e| json_parse_error(MEMORY_SOURCE, e))?;
+  let mut report = ImportReport::default();
+  for (index, value) in keybindings.into_iter().enumerate() {
+    let entry = value.to_string();
+    match import_keybinding(value) {
+      Ok((key_map, command)) => {
+        if let Some(command) = command.filter(|c| !report.data.commands.iter().any(|o| o.name == c.name)) {
+          report.data.commands.push(command);
+        }
+        report.data.key_maps.push(key_map);
+      }
+      Err(reason) => report.unsupported.push(Unsupported { position: index + 1, entry, reason }),
+    }
+  }
+  Ok(report)
+}
+
+fn import_keybinding(value: serde_json::Value) -> Result<(KeyMap, Option<Command>), String> {
+  let keybinding: Keybinding = serde_json::from_value(value).map_err(|e| e.to_string())?;
+  if keybinding.key.trim().is_empty() || keybinding.command.trim().is_empty() {
+    return Err("key and command are required".to_owned());
+  }
+  if keybinding.args.is_some() {
+    return Err("commands with args are not supported".to_owned());
+  }
+  let keys = keybinding.key.split_whitespace().map(vscode_chord_to_vim).collect::<Result<Vec<String>, KeyMapError>>()
+    .map_err(|e| e.to_string())?;
+
+  if let Some(command) = keybinding.command.strip_prefix('-') {
+    let mut key_map = KeyMap::new(&[], command);
+    key_map.keys = keys;
+    key_map.unbind = true;
+    return Ok((key_map, None));
+  }
+  let mut key_map = KeyMap::new(&[], &keybinding.command);
+  key_map.keys = keys;
+  key_map.command_type = KeyMapCommandType::Command;
+  if let Some(when) = keybinding.when {
+    key_map.when = translate_when(&when)?;
+  }
+  let command = Command {
+    name: keybinding.command.clone(),
+    commands: vec![keybinding.command],
+    command_type: CommandType::FunctionSequence,
+    ..Command::default()
+  };
+  Ok((key_map, Some(command)))
+}
+
+/// translates a VS Code when clause into a logical_expr expression.
+/// Context keys, true/false, !, &&, ||, ==, != and parentheses are supported, the right side of == and != is a string.
+/// Regex matches, in, not in and numeric comparisons are not.
+fn translate_when(when: &str) -> Result<String, String> {
+  let tokens = tokenize_when(when)?;
+  let mut result = String::new();
+  let mut is_value = false; // the token follows == or !=
+  for token in tokens {
+    let translated = match token.as_str() {
+      "=~" | "<" | ">" | "<=" | ">=" | "in" | "not" => return Err(format!("when operator {token} is not supported: {when}")),
+      "&&" | "||" | "==" | "!=" | "(" | ")" | "!" => token.clone(),
+      _ if is_value && token.starts_with('\'') => format!("\"{}\"", token.trim_matches('\'')),
+      _ if is_value => format!("\"{token}\""),
+      _ if token.starts_with('\'') => return Err(format!("string without comparison in when clause: {when}")),
+      _ => token.clone(),
+    };
+    is_value = token == "==" || token == "!=";
+    if !result.is_empty() && !result.ends_with(['!', '(']) && translated != ")" {
+      result.push(' ');
+    }
+    result.push_str(&translated);
+  }
+  if result.is_empty() {
+    return Ok("true".to_owned());
+  }
+  Ok(result)
+}
+
+fn tokenize_when(when: &str) -> Result<Vec<String>, String> {
+  let mut tokens = Vec::new();
+  let mut rest = when.trim_start();
+  while let Some(c) = rest.chars().next() {
+    let length = match c {
+      '\'' => rest[1..].find('\'').ok_or(format!("unterminated string in when clause: {when}"))? + 2,
+      '(' | ')' => 1,
+      _ if ["&&", "||", "==", "!=", "=~", "<=", ">="].iter().any(|op| rest.starts_with(op)) => 2,
+      '!' | '<' | '>' => 1,
+      _ => rest.find(|c: char| c.is_whitespace() || "()!=<>&|'".contains(c)).unwrap_or(rest.len()),
+    };
+    if length == 0 {
+      return Err(format!("unexpected {c} in when clause: {when}"));
+    }
+    tokens.push(rest[..length].to_owned());
+    rest = rest[length..].trim_start();
+  }
+  Ok(tokens)
+}
+
+// removes // and /* */ comments as well as trailing commas, keeping line breaks for error positions
+fn strip_jsonc(json: &str) -> String {
+  let mut result = String::with_capacity(json.len());
+  let mut chars = json.chars().peekable();
+  let mut in_string = false;
+  while let Some(c) = chars.next() {
+    match c {
+      '"' => in_string = !in_string,
+      '\\' if in_string => {
+        result.push(c);
+        result.extend(chars.next());
+        continue;
+      }
+      '/' if !in_string && chars.peek() == Some(&'/') => {
+        while chars.peek().is_some_and(|c| *c != '\n') {
+          chars.next();
+        }
+        continue;
+      }
+      '/' if !in_string && chars.peek() == Some(&'*') => {
+        chars.next();
+        let mut previous = ' ';
+        for c in chars.by_ref() {
+          if c == '\n' {
+            result.push('\n');
+          }
+          if previous == '*' && c == '/' {
+            break;
+          }
+          previous = c;
+        }
+        continue;
+      }
+      ']' | '}' if !in_string => {
+        let trimmed = result.trim_end().len();
+        if result[..trimmed].ends_with(',') {
+          result.replace_range(trimmed - 1..trimmed, " ");
+        }
+      }
+      _ => {},
+    }
+    result.push(c);
+  }
+  result
+}
+
+#[test]
+fn from_keybindings_test() {
+  let report = from_keybindings(r#"[
+    // comments and trailing commas as written by VS Code
+    { "key": "ctrl+k ctrl+c", "command": "editor.action.addCommentLine", "when": "editorTextFocus && !editorReadonly" },
+    { "key": "ctrl+shift+k", "command": "editor.action.deleteLines", "when": "editorLangId == 'rust' || resourceExtname != .rs" },
+    /* removal */
+    { "key": "ctrl+d", "command": "-editor.action.addSelectionToNextFindMatch" },
+    { "key": "f5", "command": "workbench.action.debug.start", "when": "resourceFilename =~ /test/" },
+    { "key": "ctrl+1", "command": "workbench.action.openEditorAtIndex", "args": 1 },
+    { "key": "ctrl+foo+x", "command": "broken" },
+  ]"#).unwrap();
+
+  let key_maps = &report.data.key_maps;
+  assert_eq!(key_maps.len(), 3);
+  assert_eq!(key_maps[0].keys, vec!["<C-k>", "<C-c>"]);
+  assert_eq!(key_maps[0].when, "editorTextFocus && !editorReadonly");
+  assert_eq!(key_maps[1].keys, vec!["<C-S-k>"]);
+  assert_eq!(key_maps[1].when, "editorLangId == \"rust\" || resourceExtname != \".rs\"");
+  assert!(key_maps[2].unbind);
+  assert_eq!(key_maps[2].command, "editor.action.addSelectionToNextFindMatch");
+  assert_eq!(report.data.commands.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["editor.action.addCommentLine", "editor.action.deleteLines"]);
+  assert_eq!(report.unsupported.iter().map(|u| u.position).collect::<Vec<_>>(), vec![4, 5, 6]);
+
+  assert!(matches!(from_keybindings("[{]"), Err(KeyMapError::JsonParse { .. })));
+}
+
+#[test]
+fn translate_when_test() {
+  assert_eq!(translate_when("(a || b) && !c"), Ok("(a || b) && !c".to_owned()));
+  assert_eq!(translate_when("config.editor.x == 'a b'"), Ok("config.editor.x == \"a b\"".to_owned()));
+  assert!(translate_when("a in b").is_err());
+  assert!(translate_when("a > 2").is_err());
+  assert!(translate_when("'a").is_err());
+}
+
+#[test]
+fn import_removal_unbinds_defaults_test() {
+  use crate::environment::DefaultEnvironment;
+  use crate::evaluation_tree::from_key_map_data::apply_removals;
+  use crate::json_parser::KeyMapData;
+  use crate::types::{FunctionString, KeyPress};
+
+  let mut data: KeyMapData = serde_json::from_str(r#"{"key_maps": [
+    {"keys": ["<c-d>"], "command": "editor.action.addSelectionToNextFindMatch"},
+    {"keys": "<c-k><c-c>", "command": "editor.action.addCommentLine"},
+    {"keys": "ctrl+k ctrl+u", "command": "editor.action.removeCommentLine"}
+  ]}"#).unwrap();
+  let report = from_keybindings(r#"[
+    { "key": "ctrl+d", "command": "-editor.action.addSelectionToNextFindMatch" },
+    { "key": "ctrl+k ctrl+c", "command": "-editor.action.addCommentLine" }
+  ]"#).unwrap();
+  data.merge_layer(report.data);
+  apply_removals::<KeyPress, FunctionString, _>(&mut data, &DefaultEnvironment::new());
+  assert_eq!(data.key_maps.iter().map(|k| k.command.as_str()).collect::<Vec<_>>(), vec!["editor.action.removeCommentLine"]);
+}
@@ -1,8 +1,13 @@
+use std::io::Read;
 use std::path::Path;
 use std::fs;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::KeyMapError;
+use crate::types::split_key_sequence;
 
 // this reads the folder structure at root and expects json files containing commands and key maps.
+// With the cargo features toml and yaml, .toml, .yaml and .yml files with the same structure are read as well.
 // command names are prefixed by the path_to_folder, the file names are ignored. starting with no prefix in the root folder.
 // All json files in the root and sub folders are merged into one key map data struct.
 // the json should look like this, where optional fields are set to defaults if missing
@@ -10,7 +15,7 @@ use serde::{Deserialize, Serialize};
 //   "commands": [                                      //optional defaults to empty vec
 //     {
 //       "name": "command_one",
-//       "command": ["function_one", "function_only"],
+//       "command": ["function_one", "function_only"], // at least one, only removals may leave it out
 //       "when": "true",                                // optional defaults to "true" which means always
 //       "mode": "FunctionSequence"                     // optional defaults to mixed
 //     },
@@ -19,71 +24,292 @@ use serde::{Deserialize, Serialize};
 //       "command": ["function_one", "command_one"],
 //       "when": "true",                                // optional defaults to "true" which means always
 //       "mode": "Mixed"                                // optional defaults to mixed
+//     },
+//     {
+//       "name": "context_dependent",
+//       "commands": ["command_one", "command_two"],
+//       "command_type": "CommandGroup",
+//       "dispatch": "First",                           // optional defaults to All. All, First or Priority
+//       "priority": 0,                                 // optional defaults to 0, used by parent groups with Priority dispatch
+//       "title": "Context dependent",                  // optional
+//       "description": "runs the first applicable command", // optional
+//       "category": "General",                         // optional
+//       "tags": ["context"]                            // optional defaults to empty vec
+//     },
+//     {
+//       "name": "command_three",
+//       "remove": true                                 // removes the earlier defined command_three and all key maps bound to it
+//                                                      // Mixed key maps stay if a function named command_three exists
 //     }
 //   ],
 //   "key_maps": [                                      //optional defaults to empty vec
 //     {
-//       "keys": ["a"],
-//       "command": "command_one",
-//       "mode": "Command"                              // optional defaults to mixed
+//       "keys": ["a"],                                 // or a single string like "<C-k><C-c>", "gg" or "ctrl+k ctrl+c"
+//       "command": "command_one",                      // a command or function name, see command_type
+//       "command_type": "Command",                     // optional defaults to Mixed. Command, Function or Mixed (command if one exists, else function)
+//       "mode": ["Normal"],                            // optional defaults to ["Normal"]
+//       "when": "true",                                // optional defaults to "true". The same keys can be bound several times with different when expressions
+//       "description": "runs command one"              // optional, title, description, category and tags are allowed on key maps and commands
+//     },
+//     {
+//       "keys": ["b"],
+//       "command": "command_two",                      // optional for unbind. If set only bindings to this command are removed
+//       "mode": ["Normal"],
+//       "unbind": true                                 // removes earlier bindings of the keys in the modes. "command": "-command_two" does the same
 //     }
 //   ]
 //}
-pub fn key_map_data_from_path(root: &Path) -> KeyMapData {
-  let contents = read_all_json_files(root);
+// Loading never panics. Every unreadable directory, unreadable file, non utf-8 path and malformed json is collected,
+// such that all broken files are reported at once.
+pub fn key_map_data_from_path(root: &Path) -> Result<KeyMapData, KeyMapError> {
+  let mut errors = Vec::new();
+  let contents = read_all_key_map_files(root, &mut errors);
   let mut data = KeyMapData::default();
   for (path, json) in contents {
-    let relative_path = get_relative_path(root, &path);
-    let name_extend = convert_relative_path_to_string(relative_path);
-    let data_to_add = parse_key_map_json(json, &name_extend);
-    data.commands.extend(data_to_add.commands);
-    data.key_maps.extend(data_to_add.key_maps);
+    let name_extend = get_relative_path(root, path.parent().unwrap_or(root))
+      .and_then(convert_relative_path_to_string);
+    let data_to_add = name_extend.and_then(|name_extend| parse_key_map_file(json, &name_extend, &path));
+    match data_to_add {
+      Ok(data_to_add) => {
+        data.commands.extend(data_to_add.commands);
+        data.key_maps.extend(data_to_add.key_maps);
+      }
+      Err(error) => errors.push(error),
+    }
+  }
+  KeyMapError::collect(errors)?;
+  Ok(data)
+}
+
+/// a configuration layer, e.g. built-in defaults, user config or project config.
+/// Layers are loaded in order, later layers override commands and rebind keys of earlier ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer {
+  pub name: String,
+  pub path: String,
+}
+
+impl Layer {
+  pub fn new(name: &str, path: &str) -> Self {
+    Self { name: name.to_owned(), path: path.to_owned() }
   }
-  data
 }
 
-#[derive(Deserialize, Serialize, Debug, Default)]
-pub(crate) struct KeyMapData {
+// the first layer has to exist, all following layers are optional and skipped if their directory does not exist.
+pub fn key_map_data_from_layers(layers: &[Layer]) -> Result<KeyMapData, KeyMapError> {
+  let mut data = KeyMapData::default();
+  merge_layers_from_path(&mut data, layers, true)?;
+  Ok(data)
+}
+
+// loads the layers on top of data, e.g. on top of key maps embedded in the binary
+pub fn merge_layers_from_path(data: &mut KeyMapData, layers: &[Layer], first_required: bool) -> Result<(), KeyMapError> {
+  let mut errors = Vec::new();
+  for (index, layer) in layers.iter().enumerate() {
+    let path = Path::new(&layer.path);
+    if (index > 0 || !first_required) && !path.exists() {
+      continue;
+    }
+    match key_map_data_from_path(path) {
+      Ok(mut layer_data) => {
+        layer_data.commands.iter_mut().for_each(|c| c.layer = layer.name.clone());
+        layer_data.key_maps.iter_mut().for_each(|k| k.layer = layer.name.clone());
+        data.merge_layer(layer_data);
+      }
+      Err(KeyMapError::Multiple(layer_errors)) => errors.extend(layer_errors),
+      Err(error) => errors.push(error),
+    }
+  }
+  KeyMapError::collect(errors)
+}
+
+/// the content of one or several key map json files, see the schema above.
+/// Can be built in code or parsed from strings, e.g. key maps embedded with include_str!
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct KeyMapData {
   #[serde(default)]
-  pub(crate) commands: Vec<Command>,
+  pub commands: Vec<Command>,
   #[serde(default)]
-  pub(crate) key_maps: Vec<KeyMap>,
+  pub key_maps: Vec<KeyMap>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-pub(crate) struct Command {
-  pub(crate) name: String,
-  pub(crate) commands: Vec<String>,
+impl KeyMapData {
+  pub fn from_json_str(json: &str) -> Result<Self, KeyMapError> {
+    serde_json::from_str(json).map_err(|e| json_parse_error(MEMORY_SOURCE, e))
+  }
+  pub fn from_json_value(json: serde_json::Value) -> Result<Self, KeyMapError> {
+    serde_json::from_value(json).map_err(|e| json_parse_error(MEMORY_SOURCE, e))
+  }
+  pub fn from_reader<R: Read>(reader: R) -> Result<Self, KeyMapError> {
+    serde_json::from_reader(reader).map_err(|e| json_parse_error(MEMORY_SOURCE, e))
+  }
+  /// prefixes all command names like a json file in the sub folder prefix would, e.g. "sub" turns command_one into sub_command_one
+  pub fn with_prefix(mut self, prefix: &str) -> Self {
+    if !prefix.is_empty() {
+      self.commands.iter_mut().for_each(|c| c.name = format!("{}_{}", prefix, c.name));
+    }
+    self
+  }
+
+  /// commands of the later layer replace commands with the same name, key maps are appended and override on insertion
+  pub(crate) fn merge_layer(&mut self, other: KeyMapData) {
+    self.commands.retain(|c| !other.commands.iter().any(|o| o.name == c.name));
+    self.commands.extend(other.commands);
+    self.key_maps.extend(other.key_maps);
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Command {
+  pub name: String,
+  #[serde(default)]
+  pub commands: Vec<String>,
   #[serde(default)]
-  pub(crate) command_type: CommandType,
+  pub command_type: CommandType,
   #[serde(default = "default_when")]
-  pub(crate) when: String,
+  pub when: String,
+  #[serde(default)]
+  pub dispatch: GroupDispatch, // only used by CommandGroup
+  #[serde(default)]
+  pub priority: i32, // used by a parent CommandGroup with Priority dispatch
+  #[serde(flatten)]
+  pub metadata: Metadata,
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub remove: bool, // removes the earlier defined command with this name and its key maps
+  // the file and layer this command was read from, used for diagnostics. Key maps keep the same
+  #[serde(skip)]
+  pub(crate) source: String,
+  #[serde(skip)]
+  pub(crate) layer: String,
+}
+
+// the same defaults as for missing json fields
+impl Default for Command {
+  fn default() -> Self {
+    Self {
+      name: String::new(),
+      commands: Vec::new(),
+      command_type: CommandType::default(),
+      when: default_when(),
+      dispatch: GroupDispatch::default(),
+      priority: 0,
+      metadata: Metadata::default(),
+      remove: false,
+      source: String::new(),
+      layer: String::new(),
+    }
+  }
 }
 
 fn default_when() -> String { "true".to_owned() }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-pub(crate) struct KeyMap {
-  pub(crate) keys: Vec<String>,
-  pub(crate) command: String,
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyMap {
+  #[serde(deserialize_with = "deserialize_keys")]
+  pub keys: Vec<String>,
+  #[serde(default)]
+  pub command: String,
   #[serde(default)]
-  pub(crate) command_type: KeyMapCommandType,
+  pub command_type: KeyMapCommandType,
   #[serde(default = "default_mode")]
-  pub(crate) mode: Vec<String>,
+  pub mode: Vec<String>,
+  #[serde(default = "default_when")]
+  pub when: String,
+  #[serde(flatten)]
+  pub metadata: Metadata,
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub unbind: bool, // removes earlier bindings of the keys in the modes, only those to command if it is set
+  #[serde(skip)]
+  pub(crate) source: String,
+  #[serde(skip)]
+  pub(crate) layer: String,
+}
+
+impl KeyMap {
+  /// binds keys to a command or function in Normal mode, the other fields are set like missing json fields
+  pub fn new(keys: &[&str], command: &str) -> Self {
+    Self { keys: keys.iter().map(|k| k.to_string()).collect(), command: command.to_owned(), ..Self::default() }
+  }
+}
+
+impl Default for KeyMap {
+  fn default() -> Self {
+    Self {
+      keys: Vec::new(),
+      command: String::new(),
+      command_type: KeyMapCommandType::default(),
+      mode: default_mode(),
+      when: default_when(),
+      metadata: Metadata::default(),
+      unbind: false,
+      source: String::new(),
+      layer: String::new(),
+    }
+  }
 }
 
 fn default_mode() -> Vec<String> { vec!["Normal".to_owned()] }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-pub(crate) enum CommandType {
-  CommandGroup, // will execute all commands with satisfied when expression (usually only one)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeysNotation {
+  Keys(Vec<String>),
+  Sequence(String),
+}
+
+// keys are a list of keys or a single string like "<C-k><C-c>", "gg" or "ctrl+k ctrl+c"
+fn deserialize_keys<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+  match KeysNotation::deserialize(deserializer)? {
+    KeysNotation::Keys(keys) => Ok(keys),
+    KeysNotation::Sequence(sequence) => split_key_sequence(&sequence).map_err(serde::de::Error::custom),
+  }
+}
+
+/// optional human readable information on commands and key maps, e.g. for command palettes and cheat sheets
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metadata {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub title: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub category: Option<String>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub tags: Vec<String>,
+}
+
+impl Metadata {
+  /// fills every missing field with the one of other, tags are merged
+  pub fn or(&self, other: &Metadata) -> Metadata {
+    let mut tags = self.tags.clone();
+    tags.extend(other.tags.iter().filter(|t| !self.tags.contains(t)).cloned());
+    Metadata {
+      title: self.title.clone().or(other.title.clone()),
+      description: self.description.clone().or(other.description.clone()),
+      category: self.category.clone().or(other.category.clone()),
+      tags,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CommandType {
+  CommandGroup, // executes the commands with satisfied when expression, which ones depends on the GroupDispatch
   FunctionSequence, // executes the function in sequence
   #[default]
   Mixed, // executes functions and commands (if when expression is satisfied) in sequence
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-pub(crate) enum KeyMapCommandType {
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GroupDispatch {
+  #[default]
+  All, // executes all commands with satisfied when expression in sequence
+  First, // executes only the first command with satisfied when expression
+  Priority, // executes only the command with satisfied when expression and highest priority, the first one on ties
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum KeyMapCommandType {
   Command,
   Function,
   #[default]
@@ -92,40 +318,102 @@ pub(crate) enum KeyMapCommandType {
 
 //------------------------------------------
 
-fn read_all_json_files(dir: &Path) -> Vec<(Box<Path>, String)> {
-    let entries = fs::read_dir(dir).unwrap();
+/// json files and, depending on the enabled features, toml and yaml files
+pub(crate) fn is_key_map_file(path: &Path) -> bool {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    matches!(extension, Some("json"))
+        || (cfg!(feature = "toml") && matches!(extension, Some("toml")))
+        || (cfg!(feature = "yaml") && matches!(extension, Some("yaml") | Some("yml")))
+}
+
+fn read_all_key_map_files(dir: &Path, errors: &mut Vec<KeyMapError>) -> Vec<(Box<Path>, String)> {
     let mut result: Vec<(Box<Path>, String)> = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            errors.push(KeyMapError::io(dir, e));
+            return result;
+        }
+    };
     for entry in entries {
-        let entry = entry.unwrap();
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                errors.push(KeyMapError::io(dir, e));
+                continue;
+            }
+        };
         let path = entry.path();
         if path.is_dir() {
-            result.extend(read_all_json_files(&path));
-        } else {
-            if let Some(ext) = path.extension() {
-                if ext == "json" {
-                    let relative_path = dir;
-                    let content = fs::read_to_string(path).unwrap();
-                    result.push((relative_path.into(), content));
-                }
+            result.extend(read_all_key_map_files(&path, errors));
+        } else if is_key_map_file(&path) {
+            match fs::read_to_string(&path) {
+                Ok(content) => result.push((path.into(), content)),
+                Err(e) => errors.push(KeyMapError::io(&path, e)),
             }
         }
     }
     result
 }
 
-fn get_relative_path<'a>(root: &'a Path, path: &'a Path) -> &'a Path {
-    path.strip_prefix(root).unwrap()
+fn get_relative_path<'a>(root: &'a Path, path: &'a Path) -> Result<&'a Path, KeyMapError> {
+    path.strip_prefix(root).map_err(|_| KeyMapError::InvalidPath(path.display().to_string()))
 }
 
-fn convert_relative_path_to_string(path: &Path) -> String {
-    path.to_str().unwrap().to_owned().replace("\\", "_").replace("/", "_")
+fn convert_relative_path_to_string(path: &Path) -> Result<String, KeyMapError> {
+    let path_str = path.to_str().ok_or(KeyMapError::InvalidPath(path.display().to_string()))?;
+    Ok(path_str.replace("\\", "_").replace("/", "_"))
 }
 
-fn parse_key_map_json(json_string: String, name_extend: &str) -> KeyMapData {
-  let mut data: KeyMapData = serde_json::from_str(&json_string).unwrap();
-  if name_extend != "" {
-    data.commands.iter_mut().for_each(|c| c.name = format!("{}_{}", name_extend, c.name));
+// reported as path of json which was not read from a file
+pub(crate) const MEMORY_SOURCE: &str = "<memory>";
+
+pub(crate) fn json_parse_error(path: &str, e: serde_json::Error) -> KeyMapError {
+  KeyMapError::JsonParse { path: path.to_owned(), line: e.line(), column: e.column(), message: e.to_string() }
+}
+
+#[cfg(feature = "toml")]
+fn toml_parse_error(path: &str, content: &str, e: toml::de::Error) -> KeyMapError {
+  // toml reports a byte span, lines and columns start at 1 and columns count characters like for json
+  let offset = e.span().map_or(0, |span| span.start);
+  let before = content.get(..offset.min(content.len())).unwrap_or(content);
+  let line = before.matches('\n').count() + 1;
+  let column = before[before.rfind('\n').map_or(0, |i| i + 1)..].chars().count() + 1;
+  KeyMapError::Parse { path: path.to_owned(), line, column, message: e.message().to_owned() }
+}
+
+#[cfg(feature = "yaml")]
+fn yaml_parse_error(path: &str, e: serde_yaml::Error) -> KeyMapError {
+  // errors without a location point at the start of the file, lines and columns start at 1 like for json
+  let (line, column) = e.location().map_or((1, 1), |l| (l.line(), l.column()));
+  KeyMapError::Parse { path: path.to_owned(), line, column, message: e.to_string() }
+}
+
+fn parse_key_map_file(content: String, name_extend: &str, path: &Path) -> Result<KeyMapData, KeyMapError> {
+  match path.extension().and_then(|ext| ext.to_str()) {
+    #[cfg(feature = "toml")]
+    Some("toml") => {
+      let data: KeyMapData = toml::from_str(&content).map_err(|e| toml_parse_error(&path.display().to_string(), &content, e))?;
+      Ok(with_source(data.with_prefix(name_extend), path))
+    }
+    #[cfg(feature = "yaml")]
+    Some("yaml") | Some("yml") => {
+      let data: KeyMapData = serde_yaml::from_str(&content).map_err(|e| yaml_parse_error(&path.display().to_string(), e))?;
+      Ok(with_source(data.with_prefix(name_extend), path))
+    }
+    _ => parse_key_map_json(content, name_extend, path),
   }
+}
+
+fn parse_key_map_json(json_string: String, name_extend: &str, path: &Path) -> Result<KeyMapData, KeyMapError> {
+  let data: KeyMapData = serde_json::from_str(&json_string).map_err(|e| json_parse_error(&path.display().to_string(), e))?;
+  Ok(with_source(data.with_prefix(name_extend), path))
+}
+
+fn with_source(mut data: KeyMapData, path: &Path) -> KeyMapData {
+  let source = path.display().to_string();
+  data.commands.iter_mut().for_each(|c| c.source = source.clone());
+  data.key_maps.iter_mut().for_each(|k| k.source = source.clone());
   data
 }
 
@@ -135,15 +423,24 @@ fn parse_key_map_json(json_string: String, name_extend: &str) -> KeyMapData {
 
 #[test]
 fn read_dir() {
-  let result = read_all_json_files(Path::new("./src/key_maps"));
-  let (path, json) = &result[1];
-  println!("{:?}, {}", path, json);
+  let mut errors = Vec::new();
+  let result = read_all_key_map_files(Path::new("./key_maps"), &mut errors);
+  assert!(errors.is_empty());
+  assert_eq!(result.len(), 2);
+}
+
+#[test]
+fn read_missing_dir() {
+  let mut errors = Vec::new();
+  let result = read_all_key_map_files(Path::new("./does_not_exist"), &mut errors);
+  assert!(result.is_empty());
+  assert!(matches!(errors.as_slice(), [KeyMapError::Io { .. }]));
 }
 
 #[test]
 fn convert() {
     let path = Path::new("sub/map.json");
-    let result = convert_relative_path_to_string(path);
+    let result = convert_relative_path_to_string(path).unwrap();
     assert_eq!(result, "sub_map.json")
 }
 
@@ -151,19 +448,149 @@ fn convert() {
 fn get_relative_path_test() {
     let root = Path::new("./src/key_maps");
     let path = Path::new("./src/key_maps/sub/some");
-    let result = get_relative_path(root, path);
+    let result = get_relative_path(root, path).unwrap();
     assert_eq!(result, Path::new("sub/some"))
 }
 
 #[test]
 fn parse_key_map_json_test() {
-  let json_string = fs::read_to_string("./src/key_maps/simple.json").unwrap();
-  let c: KeyMapData = parse_key_map_json(json_string, "extension");
-  println!("{:?}", c);
+  let path = Path::new("./key_maps/simple.json");
+  let json_string = fs::read_to_string(path).unwrap();
+  let c: KeyMapData = parse_key_map_json(json_string, "extension", path).unwrap();
+  assert!(c.commands.iter().all(|c| c.name.starts_with("extension_")));
+}
+
+#[test]
+fn parse_key_map_json_error_test() {
+  let json_string = "{\n  \"commands\": [\n    {\"name\": 1}\n  ]\n}".to_owned();
+  let error = parse_key_map_json(json_string, "", Path::new("broken.json")).unwrap_err();
+  match error {
+    KeyMapError::JsonParse { path, line, .. } => {
+      assert_eq!(path, "broken.json");
+      assert_eq!(line, 3);
+    }
+    e => panic!("unexpected error: {e:?}"),
+  }
 }
 
 #[test]
 fn keymapdata_from_path_test() {
-  let data = key_map_data_from_path(Path::new("./src/key_maps"));
+  let data = key_map_data_from_path(Path::new("./key_maps")).unwrap();
   println!("{:#?}", data);
-}
\ No newline at end of file
+}
+
+#[test]
+fn keymapdata_from_path_collects_all_errors_test() {
+  let root = std::env::temp_dir().join("key_map_collect_errors_test");
+  let _ = fs::remove_dir_all(&root);
+  fs::create_dir_all(root.join("sub")).unwrap();
+  fs::write(root.join("broken.json"), "{ \"commands\": [ }").unwrap();
+  fs::write(root.join("sub").join("broken.json"), "{ \"key_maps\": 1 }").unwrap();
+  fs::write(root.join("sub").join("fine.json"), "{}").unwrap();
+  let error = key_map_data_from_path(&root).unwrap_err();
+  fs::remove_dir_all(&root).unwrap();
+  match error {
+    KeyMapError::Multiple(errors) => {
+      assert_eq!(errors.len(), 2);
+      assert!(errors.iter().all(|e| matches!(e, KeyMapError::JsonParse { .. })));
+    }
+    e => panic!("unexpected error: {e:?}"),
+  }
+}
+
+#[test]
+fn keymapdata_from_layers_test() {
+  let root = std::env::temp_dir().join("key_map_layers_test");
+  let _ = fs::remove_dir_all(&root);
+  fs::create_dir_all(root.join("user")).unwrap();
+  fs::write(root.join("user").join("user.json"), r#"{
+    "commands": [{"name": "command_one", "commands": ["function_two"]}],
+    "key_maps": [{"keys": ["c"], "command": "command_one"}]
+  }"#).unwrap();
+  let layers = vec![
+    Layer::new("default", "./key_maps"),
+    Layer::new("user", root.join("user").to_str().unwrap()),
+    Layer::new("project", root.join("missing").to_str().unwrap()),
+  ];
+  let data = key_map_data_from_layers(&layers).unwrap();
+  fs::remove_dir_all(&root).unwrap();
+  let command_one: Vec<&Command> = data.commands.iter().filter(|c| c.name == "command_one").collect();
+  assert_eq!(command_one.len(), 1);
+  assert_eq!(command_one[0].commands, vec!["function_two".to_owned()]);
+  assert_eq!(command_one[0].layer, "user");
+  assert_eq!(data.key_maps.last().map(|k| k.layer.as_str()), Some("user"));
+  assert!(data.key_maps.iter().any(|k| k.layer == "default"));
+  assert!(key_map_data_from_layers(&[Layer::new("default", "./does_not_exist")]).is_err());
+}
+
+#[test]
+fn keymapdata_from_memory_test() {
+  let json = r#"{"commands": [{"name": "command_one", "commands": ["function_one"]}], "key_maps": [{"keys": ["a"], "command": "sub_command_one"}]}"#;
+  let data = KeyMapData::from_json_str(json).unwrap().with_prefix("sub");
+  assert_eq!(data.commands[0].name, "sub_command_one");
+  assert_eq!(data.key_maps[0].mode, vec!["Normal".to_owned()]);
+  assert_eq!(data.key_maps[0].when, "true");
+
+  let from_reader = KeyMapData::from_reader(json.as_bytes()).unwrap();
+  let from_value = KeyMapData::from_json_value(serde_json::from_str(json).unwrap()).unwrap();
+  assert_eq!(from_reader.commands[0].name, "command_one");
+  assert_eq!(from_value.key_maps[0].keys, vec!["a".to_owned()]);
+  assert_eq!(KeyMap::default().when, data.key_maps[0].when);
+
+  let error = KeyMapData::from_json_str(r#"{"key_maps": [{"keys": "#).unwrap_err();
+  assert!(matches!(error, KeyMapError::JsonParse { ref path, line: 1, .. } if path == MEMORY_SOURCE));
+}
+
+#[cfg(all(feature = "toml", feature = "yaml"))]
+#[test]
+fn keymapdata_from_toml_and_yaml_test() {
+  let root = std::env::temp_dir().join("key_map_toml_yaml_test");
+  let _ = fs::remove_dir_all(&root);
+  fs::create_dir_all(root.join("sub")).unwrap();
+  fs::write(root.join("map.toml"), r#"
+[[commands]]
+name = "save"
+commands = ["function_one"]
+title = "Save"
+
+[[key_maps]]
+keys = ["s"]
+command = "sub_quit"
+"#).unwrap();
+  fs::write(root.join("sub").join("map.yml"), "commands:\n  - name: quit\n    commands: [function_two]\n").unwrap();
+  let mut data = key_map_data_from_path(&root).unwrap();
+  data.commands.sort_by(|a, b| a.name.cmp(&b.name));
+  assert_eq!(data.commands.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["save", "sub_quit"]);
+  assert_eq!(data.commands[0].metadata.title.as_deref(), Some("Save"));
+  assert_eq!(data.key_maps[0].mode, vec!["Normal".to_owned()]);
+  assert!(data.commands[1].source.ends_with("map.yml"));
+
+  fs::write(root.join("broken.toml"), "[[commands]]\nname = \n").unwrap();
+  fs::write(root.join("sub").join("broken.yaml"), "commands: [\n").unwrap();
+  let error = key_map_data_from_path(&root).unwrap_err();
+  fs::remove_dir_all(&root).unwrap();
+  let KeyMapError::Multiple(mut errors) = error else { panic!("expected both broken files to be reported") };
+  errors.sort_by_key(|e| e.to_string());
+  assert!(matches!(errors[0], KeyMapError::Parse { ref path, line: 2, .. } if path.ends_with("broken.toml")));
+  let error = toml_parse_error("umlaut.toml", "key = \"äöü\" x", toml::from_str::<toml::Value>("key = \"äöü\" x").unwrap_err());
+  assert!(matches!(error, KeyMapError::Parse { line: 1, column: 13, .. }), "{error}");
+  assert!(matches!(errors[1], KeyMapError::Parse { ref path, line, column, .. } if path.ends_with("broken.yaml") && line >= 1 && column >= 1));
+}
+
+#[test]
+fn key_sequence_string_test() {
+  let data = KeyMapData::from_json_str(r#"{"key_maps": [
+    {"keys": "<C-k><C-c>", "command": "a"},
+    {"keys": "gg", "command": "b"},
+    {"keys": "ctrl+k ctrl+c", "command": "c"},
+    {"keys": ["<c-k>", "<c-c>"], "command": "d"}
+  ]}"#).unwrap();
+  let keys: Vec<Vec<String>> = data.key_maps.into_iter().map(|k| k.keys).collect();
+  assert_eq!(keys[0], vec!["<C-k>", "<C-c>"]);
+  assert_eq!(keys[1], vec!["g", "g"]);
+  assert_eq!(keys[2], keys[0]);
+  assert_eq!(keys[3], vec!["<c-k>", "<c-c>"]);
+
+  let error = KeyMapData::from_json_str(r#"{"key_maps": [{"keys": "<C-k", "command": "a"}]}"#).unwrap_err();
+  assert!(matches!(error, KeyMapError::JsonParse { ref message, .. } if message.contains("unclosed <")));
+}
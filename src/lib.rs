@@ -1,10 +1,16 @@
 //! key maps is a simple lib which enables key mappings. From keys onto functions.  
 //!
 //! **Features:**
-//! - configure keybindings with json files
+//! - configure keybindings with json files, or toml and yaml files with the features toml and yaml
 //! - split json into several files in a folder and sub folders
+//! - layer several folders, e.g. defaults, user and project config, where later layers override earlier ones
 //! - supports when expressions which "lookup" values in the environment
 //! - supports chained key inputs e.g. [\<c-k\>, \<c-c\>]  
+//! - types::KeyPress parses vim key notation, such that \<c-k\>, \<C-k\> and \<Ctrl-k\> are the same key
+//! - validate the json configuration, reporting all problems at once
+//! - vim like timeout for ambiguous key sequences, e.g. [g] and [g, g]
+//! - build key maps in code with the KeyMapBuilder and add or remove bindings at runtime
+//! - import VS Code keybindings.json and vimrc mappings, export the final configuration as json or cheat sheet
 //!
 //! **Design:**  
 //! - Types are kept as traits to allow for loose coupling. E.g. the keys have to implement the Key trait. Which mainly consists of a conversion from string to key and hashing.
@@ -18,42 +24,75 @@
 //! The central idea is to map keys combinations onto functions. Key combinations are pressed by the use and functions are provided by the software. This lib maps one onto the other.  
 //! Between the two, commands are used as an abstraction for the mappings.  
 //! - **A Commands** contains a when expression and a list of commands and functions. When a command is called the when expression is evaluated. If true the list of commands and functions is "called". The result of such a call will be a list of functions which should be executed in sequence.
-//! - **A KeyMap** contains a list of Keys, a Mode, and single command. Most software won't make use of the Mode, but this lib was designed keeping modal editors in mind. The list of keys translates to the sequence of keys which need to be pressed to execute the command. Further only **One** command can be called by a key map. If several commands should be called by a key map, use a command as abstraction to call them all. This is deliberately chosen to facilitate rebinding of keys. E.g. mapping tab onto to will be easy since tab only calls one command.
+//! - **A KeyMap** contains a list of Keys, a Mode, and single command. Most software won't make use of the Mode, but this lib was designed keeping modal editors in mind. The list of keys translates to the sequence of keys which need to be pressed to execute the command. Further only **One** command can be called by a key map. If several commands should be called by a key map, use a command as abstraction to call them all. This is deliberately chosen to facilitate rebinding of keys. E.g. mapping tab onto to will be easy since tab only calls one command. Optionally a key map has a when expression as well, so the same keys can be bound to different commands depending on the environment. The last key map with satisfied when expression wins.
 //!
 //! **Remark** this is still some work in progress bugs are likely to appear.  
 //! **Usage**
-//! ```
+//! ```no_run
 //! use logical_expr::Context; // external crate for expression parsing.
-//! use key_maps::{KeyParser, environment::{EnvFunctions, EnvMode, EnvVariables}};
+//! use key_map::{KeyParser, KeyMapError, environment::{DefaultEnvironment, EnvFunctions, EnvMode, EnvVariables}};
+//! use key_map::types::{FunctionString, KeyCode, Mode};
 //!
-//! let mut key_parser = KeyParser::default();
-//! key_parser.set_path("path/to/json/folder");
-//! key_parser.env.set_functions(vec![Function::from("function_one"), Function::from("function_two")]); //list of all supported functions
+//! let mut key_parser: KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment> = KeyParser::default();
+//! key_parser.set_path("path/to/json/folder".to_string());
+//! key_parser.env.set_functions(vec![FunctionString::from("function_one"), FunctionString::from("function_two")]); //list of all supported functions
 //! key_parser.env.set_mode(Mode::from("Normal")); // default mode
-//! key_parser.env.set_variables(Context::new());  // used for when expressions.
-//! key_parser.init(); // parsing the json
+//! key_parser.env.set_environment_variables(Context::new());  // used for when expressions.
+//! key_parser.init().unwrap(); // parsing the json
 //!
-//! let function_list = key_parser.parse_key_sequences(vec!["<c-k>", "<c-c>"]).unwrap(); // parsing key sequences
+//! match key_parser.parse_key_sequence(&[KeyCode::from("<c-k>"), KeyCode::from("<c-c>")]) { // parsing key sequences
+//!     Ok(function_list) => println!("{function_list:?}"),
+//!     Err(KeyMapError::InvalidKeySequence(keys)) => println!("no binding for {keys:?}"),
+//!     Err(error) => println!("{error}"),
+//! }
 //! ```
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
 mod evaluation_tree;
 mod json_parser;
+pub mod builder;
+pub mod clock;
 pub mod environment;
+pub mod error;
+pub mod export;
+pub mod import;
 pub mod types;
+pub mod validation;
+#[cfg(feature = "watch")]
+pub mod watch;
 
-use std::path::Path;
+use std::time::{Duration, Instant};
 
-use json_parser::key_map_data_from_path;
+use json_parser::{key_map_data_from_layers, merge_layers_from_path};
 use evaluation_tree::{EvaluationTree};
-use evaluation_tree::from_key_map_data::try_into_evaluation_tree;
+use evaluation_tree::from_key_map_data::{apply_removals, try_into_evaluation_tree};
 use types::{FunctionString, KeyCode, Mode};
 use environment::{DefaultEnvironment, EnvFunctions, EnvMode, EnvVariables, Environment};
+pub use error::KeyMapError;
+use clock::{Clock, SystemClock};
+pub use evaluation_tree::when_expression::WhenFallback;
+pub use evaluation_tree::{Binding, NextKey};
+pub use evaluation_tree::which_key::{WhichKeyBinding, WhichKeyEntry};
+pub use evaluation_tree::reverse_lookup::CommandInfo;
+pub use builder::KeyMapBuilder;
+pub use json_parser::{Command, CommandType, GroupDispatch, KeyMap, KeyMapCommandType, KeyMapData, Layer, Metadata};
+use validation::ValidationReport;
+pub use export::CheatSheetFormat;
+// the layer of bindings added and removed at runtime
+const RUNTIME_LAYER: &str = "runtime";
+
 pub trait Key: From<&'static str> + From<String> + Clone + std::fmt::Debug + std::hash::Hash + Eq { }
 pub trait Function: From<&'static str> + From<String> + Eq + std::fmt::Display{ }
 pub struct KeyParser<M: Key, K: Key, F: Function, E: Environment<M, F>> {
     json_path: String,
+    layers: Vec<Layer>,
+    data: Option<KeyMapData>,
+    runtime: KeyMapData,
+    built: Option<KeyMapData>, // the data of the evaluation tree with removals applied, for export
     evaluation_tree: Option<EvaluationTree<M, K, F>>,
+    when_fallback: WhenFallback,
+    timeout: Option<Duration>,
+    clock: Box<dyn Clock>,
     pub env: E
 }
 
@@ -65,7 +104,24 @@ impl<K: Key> Default for KeyParser<Mode, K, FunctionString, DefaultEnvironment>
 
 impl<M: Key, K: Key, F: Function, E: Environment<M, F>> KeyParser<M, K, F, E> {
     pub fn new(json_path: String, environment: E) -> Self {
-        Self { json_path, evaluation_tree: None , env: environment }
+        Self { json_path, layers: Vec::new(), data: None, runtime: KeyMapData::default(), built: None, evaluation_tree: None, when_fallback: WhenFallback::default(), timeout: None, clock: Box::new(SystemClock), env: environment }
+    }
+    /// uses key maps from memory instead of the json path, e.g. defaults embedded in the binary.
+    /// Added layers are still loaded from their directories on top of data.
+    pub fn from_data(data: KeyMapData, environment: E) -> Self {
+        let mut key_parser = Self::new(String::new(), environment);
+        key_parser.data = Some(data);
+        key_parser
+    }
+    /// from_data with the content of a single key map json file, e.g. include_str!("key_maps.json")
+    pub fn from_json_str(json: &str, environment: E) -> Result<Self, KeyMapError> {
+        Ok(Self::from_data(KeyMapData::from_json_str(json)?, environment))
+    }
+    pub fn from_json_value(json: serde_json::Value, environment: E) -> Result<Self, KeyMapError> {
+        Ok(Self::from_data(KeyMapData::from_json_value(json)?, environment))
+    }
+    pub fn from_reader<R: std::io::Read>(reader: R, environment: E) -> Result<Self, KeyMapError> {
+        Ok(Self::from_data(KeyMapData::from_reader(reader)?, environment))
     }
     pub fn set_path(&mut self, json_path: String) {
         self.json_path = json_path
@@ -73,26 +129,240 @@ impl<M: Key, K: Key, F: Function, E: Environment<M, F>> KeyParser<M, K, F, E> {
     pub fn get_path(&mut self) -> String {
         self.json_path.clone()
     }
-    pub fn init(&mut self) -> Result<(), String>{
-        self.evaluation_tree = Some(
-            try_into_evaluation_tree::<M, K, F, E>(
-                key_map_data_from_path(Path::new(&self.json_path)),
-                &self.env)?);
+    /// adds a configuration layer on top of the json path, e.g. user or project config.
+    /// Layers are loaded in the order they are added, later layers override commands and rebind keys of earlier ones.
+    /// The directory of an added layer does not have to exist.
+    pub fn add_layer(&mut self, name: &str, path: &str) {
+        self.layers.push(Layer::new(name, path))
+    }
+    /// all layers read from directories in load order, starting with the json path as layer "default".
+    /// Without the json path if the KeyParser was created from data
+    pub fn layers(&self) -> Vec<Layer> {
+        let mut layers = match self.data {
+            Some(_) => Vec::new(),
+            None => vec![Layer::new("default", &self.json_path)],
+        };
+        layers.extend(self.layers.iter().cloned());
+        layers
+    }
+    fn key_map_data(&self) -> Result<KeyMapData, KeyMapError> {
+        let mut data = match &self.data {
+            Some(data) => {
+                let mut data = data.clone();
+                data.commands.iter_mut().for_each(|c| c.layer = "default".to_owned());
+                data.key_maps.iter_mut().for_each(|k| k.layer = "default".to_owned());
+                merge_layers_from_path(&mut data, &self.layers, false)?;
+                data
+            }
+            None => key_map_data_from_layers(&self.layers())?,
+        };
+        data.merge_layer(self.runtime.clone());
+        Ok(data)
+    }
+    /// sets what happens if a when expression can not be evaluated at key press time. Defaults to WhenFallback::Error
+    pub fn set_when_fallback(&mut self, fallback: WhenFallback) {
+        self.when_fallback = fallback;
+        if let Some(et) = &mut self.evaluation_tree {
+            et.when_fallback = fallback;
+        }
+    }
+    /// parses the json of all layers and builds the evaluation tree.
+    /// The tree is replaced only on success, on failure the previous tree stays in use.
+    pub fn init(&mut self) -> Result<(), KeyMapError>{
+        let mut data = self.key_map_data()?;
+        apply_removals::<K, F, E>(&mut data, &self.env);
+        let mut evaluation_tree = try_into_evaluation_tree::<M, K, F, E>(
+            data.clone(),
+            &self.env)?;
+        evaluation_tree.when_fallback = self.when_fallback;
+        self.evaluation_tree = Some(evaluation_tree);
+        self.built = Some(data);
         Ok(())
     }
 
-    pub fn parse_key_sequence(&self, keys: &[K]) -> Result<Vec<&F>, String> {
+    /// rebuilds the evaluation tree after the json files changed. On failure the previous tree is kept and the error returned,
+    /// on success pending keys of key by key mode are dropped.
+    pub fn reload(&mut self) -> Result<(), KeyMapError> {
+        self.init()
+    }
+    /// a watcher for the folders of all layers, see reload_if_changed
+    #[cfg(feature = "watch")]
+    pub fn watcher(&self) -> watch::FileWatcher {
+        watch::FileWatcher::new(&self.layers())
+    }
+    /// reloads if the watcher reports changed files. Returns the changes, which are empty if nothing was reloaded.
+    /// If the reload fails, the error contains the changes which triggered it
+    #[cfg(feature = "watch")]
+    pub fn reload_if_changed(&mut self, watcher: &mut watch::FileWatcher) -> Result<watch::Changes, Box<watch::ReloadError>> {
+        let changes = watcher.poll();
+        if !changes.is_empty() {
+            if let Err(error) = self.reload() {
+                return Err(Box::new(watch::ReloadError { changes, error }));
+            }
+        }
+        Ok(changes)
+    }
+    /// binds keys of an initialized KeyParser, e.g. for plugins. The binding is kept on reload, on top of all layers.
+    /// Like in the json, a key map with unbind or a "-command" removes bindings, see remove_binding
+    pub fn add_binding(&mut self, mut key_map: KeyMap) -> Result<(), KeyMapError> {
+        if let Some(command) = key_map.command.strip_prefix('-') {
+            key_map.command = command.to_owned();
+            key_map.unbind = true;
+        }
+        if key_map.unbind {
+            return self.remove_binding(key_map);
+        }
+        let et = self.evaluation_tree.as_mut().ok_or(KeyMapError::NotInitialized)?;
+        let command_names: Vec<String> = et.commands().map(|name| name.to_owned()).collect();
+        key_map.layer = RUNTIME_LAYER.to_owned();
+        et.insert_key_map(&key_map, &command_names, &self.env)?;
+        self.built.iter_mut().for_each(|built| built.key_maps.push(key_map.clone()));
+        self.runtime.key_maps.push(key_map);
+        Ok(())
+    }
+    /// removes the bindings of the keys in the modes of key_map, only those to its command if it is not empty.
+    /// Like add_binding the removal is kept on reload
+    pub fn remove_binding(&mut self, mut key_map: KeyMap) -> Result<(), KeyMapError> {
+        let et = self.evaluation_tree.as_mut().ok_or(KeyMapError::NotInitialized)?;
+        if !et.remove_key_map(&key_map) {
+            return Err(KeyMapError::InvalidKeySequence(key_map.keys));
+        }
+        key_map.unbind = true;
+        key_map.layer = RUNTIME_LAYER.to_owned();
+        if let Some(built) = &mut self.built {
+            built.key_maps.push(key_map.clone());
+            apply_removals::<K, F, E>(built, &self.env);
+        }
+        self.runtime.key_maps.push(key_map);
+        Ok(())
+    }
+    /// the final configuration after merging folders and layers, removals and overridden bindings,
+    /// including bindings added at runtime. Can be serialized to json, see export
+    pub fn export_data(&self) -> Result<KeyMapData, KeyMapError> {
+        let built = self.built.clone().ok_or(KeyMapError::NotInitialized)?;
+        Ok(export::effective_key_map_data::<K>(built))
+    }
+    /// the final configuration as key map json. Loading it from a key map folder builds the same evaluation tree
+    pub fn export(&self) -> Result<String, KeyMapError> {
+        export::to_json(&self.export_data()?)
+    }
+    /// a markdown or html cheat sheet of all bindings, grouped by mode and category
+    pub fn cheat_sheet(&self, format: CheatSheetFormat) -> Result<String, KeyMapError> {
+        Ok(export::cheat_sheet(&self.export_data()?, format))
+    }
+    /// checks the key map json for all problems at once, e.g. unknown commands, conflicting bindings or unused functions.
+    /// Unlike init this does not stop at the first error and does not change the KeyParser.
+    pub fn validate(&self) -> ValidationReport {
+        match self.key_map_data() {
+            Ok(mut data) => {
+                apply_removals::<K, F, E>(&mut data, &self.env);
+                validation::validate::<F, E>(&data, &self.env)
+            }
+            Err(error) => ValidationReport::from_load_error(error),
+        }
+    }
+
+    pub fn parse_key_sequence(&self, keys: &[K]) -> Result<Vec<&F>, KeyMapError> {
         if let Some(et) = &self.evaluation_tree {
             et.evaluate(keys, &self.env)
         } else {
-            Err("no evaluation tree. KeyParser is not initialized. Potentially due to some flawed key map json".to_string())
+            Err(KeyMapError::NotInitialized)
         }
     }
-    pub fn key_by_key(&mut self, key: K) -> Result<Option<Vec<&F>>, String> {
+    /// like vims timeoutlen: if a pressed key sequence is bound and also the prefix of a longer binding,
+    /// poll or tick will execute the shorter binding once timeout has passed. None (the default) waits for key_by_key_enter.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout
+    }
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock
+    }
+    /// returns the functions of a pending ambiguous key sequence if the timeout has passed at now.
+    /// Should be called regularly, e.g. in the event loop, before key_by_key
+    pub fn poll(&mut self, now: Instant) -> Result<Option<Vec<&F>>, KeyMapError> {
+        match (&mut self.evaluation_tree, self.timeout) {
+            (Some(et), Some(timeout)) => et.poll(now, timeout, &self.env),
+            (Some(_), None) => Ok(None),
+            (None, _) => Err(KeyMapError::NotInitialized),
+        }
+    }
+    /// poll with the current time of the clock
+    pub fn tick(&mut self) -> Result<Option<Vec<&F>>, KeyMapError> {
+        let now = self.clock.now();
+        self.poll(now)
+    }
+    pub fn key_by_key(&mut self, key: K) -> Result<Option<Vec<&F>>, KeyMapError> {
+        let now = self.clock.now();
         if let Some(et) = &mut self.evaluation_tree {
-            et.enter_key(&key, &self.env)
+            et.enter_key(&key, &self.env, now)
+        } else {
+            Err(KeyMapError::NotInitialized)
+        }
+    }
+    /// the keys pressed so far in key by key mode, e.g. to show "pending: <c-k>" in a status bar
+    pub fn pressed_keys(&self) -> &[K] {
+        match &self.evaluation_tree {
+            Some(et) => et.pressed(),
+            None => &[],
+        }
+    }
+    /// cancels the pending key sequence of key by key mode
+    pub fn cancel(&mut self) {
+        if let Some(et) = &mut self.evaluation_tree {
+            et.reset();
+        }
+    }
+    /// all keys which can be pressed next in key by key mode, with the binding they lead to and whether more keys can follow.
+    /// Without pressed keys these are the first keys of all bindings of the current mode.
+    pub fn next_keys(&self) -> Result<Vec<NextKey<K>>, KeyMapError> {
+        if let Some(et) = &self.evaluation_tree {
+            et.next_keys(&self.env)
+        } else {
+            Err(KeyMapError::NotInitialized)
+        }
+    }
+    /// executes a command by name without a key sequence, e.g. from a command palette
+    pub fn execute_command(&self, name: &str) -> Result<Vec<&F>, KeyMapError> {
+        if let Some(et) = &self.evaluation_tree {
+            et.execute_command(name, &self.env)
         } else {
-            Err("no evaluation tree. KeyParser is not initialized. Potentially due to some flawed key map json".to_string())
+            Err(KeyMapError::NotInitialized)
+        }
+    }
+    /// all commands sorted by name, with their metadata and the key sequences bound to them per mode
+    pub fn commands(&self) -> Vec<CommandInfo<M, K>> {
+        match &self.evaluation_tree {
+            Some(et) => et.command_infos(),
+            None => Vec::new(),
+        }
+    }
+    /// all key sequences of mode which trigger the command, directly or through other commands.
+    /// If active_only, bindings whose when expressions are currently not satisfied are left out
+    pub fn bindings_for_command(&self, name: &str, mode: &M, active_only: bool) -> Vec<Vec<K>> {
+        match &self.evaluation_tree {
+            Some(et) => et.bindings_for_command(name, mode, &self.env, active_only),
+            None => Vec::new(),
+        }
+    }
+    /// all key sequences per mode which eventually yield the function, e.g. to show shortcuts in menus.
+    /// If active_only, only key sequences which currently yield the function are returned
+    pub fn bindings_for_function(&self, function: &F, active_only: bool) -> Vec<(M, Vec<K>)> {
+        match &self.evaluation_tree {
+            Some(et) => et.bindings_for_function(function, &self.env, active_only),
+            None => Vec::new(),
+        }
+    }
+    /// title, description, category and tags of a command
+    pub fn command_metadata(&self, name: &str) -> Option<&Metadata> {
+        self.evaluation_tree.as_ref().and_then(|et| et.command_metadata(name))
+    }
+    /// the subtree of bindings of mode below the key prefix, for which key popups.
+    /// Bindings which resolve to no functions in the current environment are left out.
+    pub fn which_key(&self, mode: &M, prefix: &[K]) -> Result<Vec<WhichKeyEntry<K>>, KeyMapError> {
+        if let Some(et) = &self.evaluation_tree {
+            et.which_key(mode, prefix, &self.env)
+        } else {
+            Err(KeyMapError::NotInitialized)
         }
     }
     pub fn key_by_key_has_next(&mut self, key: K) -> bool {
@@ -102,11 +372,11 @@ impl<M: Key, K: Key, F: Function, E: Environment<M, F>> KeyParser<M, K, F, E> {
             false
         }
     }
-    pub fn key_by_key_enter(&mut self) -> Result<Option<Vec<&F>>, String> {
+    pub fn key_by_key_enter(&mut self) -> Result<Option<Vec<&F>>, KeyMapError> {
         if let Some(et) = &mut self.evaluation_tree {
             et.enter_key_terminate(&self.env)
         } else {
-            Err("no evaluation tree. KeyParser is not initialized. Potentially due to some flawed key map json".to_string())
+            Err(KeyMapError::NotInitialized)
         }
     }
 }
@@ -146,11 +416,212 @@ mod tests {
         let functions: Option<Vec<&FunctionString>> = kp.key_by_key_enter().unwrap();
         println!("{functions:?}");
         kp.env.set_mode(Mode::from("Insert"));
-        if let Err(msg) = kp.key_by_key(KeyCode::from("a")){
-            assert_eq!(msg, "No keybindings for mode: Mode(\"Insert\")".to_string());
+        if let Err(error) = kp.key_by_key(KeyCode::from("a")){
+            assert_eq!(error, KeyMapError::UnknownMode("Mode(\"Insert\")".to_string()));
+            assert_eq!(error.to_string(), "No keybindings for mode: Mode(\"Insert\")".to_string());
         } else{
             assert!(false)
         }
         
     }
+
+    struct FixedClock(Instant);
+    impl Clock for FixedClock {
+        fn now(&self) -> Instant {
+            self.0
+        }
+    }
+
+    #[test]
+    fn timeout_test() {
+        let start = Instant::now();
+        let mut kp: KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment> = KeyParser::default();
+        kp.env.set_functions(vec![FunctionString::from("function_one"), FunctionString::from("function_two"), FunctionString::from("funky")]);
+        kp.set_clock(Box::new(FixedClock(start)));
+        kp.set_timeout(Some(Duration::from_millis(1000)));
+        kp.init().unwrap();
+        assert_eq!(kp.key_by_key(KeyCode::from("a")).unwrap(), None); // "a" and "a b" are bound
+        assert_eq!(kp.poll(start + Duration::from_millis(500)).unwrap(), None);
+        let functions = kp.poll(start + Duration::from_millis(1000)).unwrap().unwrap();
+        assert_eq!(functions, vec![&FunctionString::from("function_one"), &FunctionString::from("function_two")]);
+        assert_eq!(kp.tick().unwrap(), None); // nothing pending anymore
+        assert_eq!(kp.key_by_key(KeyCode::from("c")).unwrap().map(|f| f.len()), Some(2));
+    }
+
+    #[test]
+    fn pending_state_test() {
+        let mut kp: KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment> = KeyParser::default();
+        kp.env.set_functions(vec![FunctionString::from("function_one"), FunctionString::from("function_two"), FunctionString::from("funky")]);
+        kp.init().unwrap();
+        let mut first_keys: Vec<String> = kp.next_keys().unwrap().iter().map(|n| format!("{:?}", n.key)).collect();
+        first_keys.sort();
+        assert_eq!(first_keys.len(), 4); // a, b, c and d
+        kp.key_by_key(KeyCode::from("a")).unwrap();
+        assert_eq!(kp.pressed_keys(), &[KeyCode::from("a")]);
+        let next_keys = kp.next_keys().unwrap();
+        assert_eq!(next_keys, vec![NextKey { key: KeyCode::from("b"), binding: Some(Binding::Command("sub_command_two".to_owned())), is_prefix: false }]);
+        assert!(next_keys[0].is_terminal());
+        kp.cancel();
+        assert!(kp.pressed_keys().is_empty());
+        assert_eq!(kp.next_keys().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn reload_keeps_tree_on_failure_test() {
+        let root = std::env::temp_dir().join("key_map_reload_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("map.json");
+        std::fs::write(&file, r#"{"key_maps": [{"keys": ["a"], "command": "function_one"}]}"#).unwrap();
+        let mut kp: KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment> = KeyParser::new(root.to_str().unwrap().to_owned(), DefaultEnvironment::with_functions(&["function_one", "function_two"]));
+        kp.init().unwrap();
+
+        std::fs::write(&file, r#"{"key_maps": [{"keys": ["a"], "command": "missing"}]}"#).unwrap();
+        assert_eq!(kp.reload(), Err(KeyMapError::CommandNotFound("missing".to_owned())));
+        assert_eq!(kp.parse_key_sequence(&[KeyCode::from("a")]), Ok(vec![&FunctionString::from("function_one")]));
+
+        std::fs::write(&file, r#"{"key_maps": [{"keys": ["a"], "command": "function_two"}]}"#).unwrap();
+        kp.reload().unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(kp.parse_key_sequence(&[KeyCode::from("a")]), Ok(vec![&FunctionString::from("function_two")]));
+    }
+
+    #[test]
+    fn from_json_str_test() {
+        let json = r#"{
+            "commands": [{"name": "command_one", "commands": ["function_one"]}],
+            "key_maps": [{"keys": ["a"], "command": "command_one"}, {"keys": ["z"], "command": "function_two"}]
+        }"#;
+        let mut kp: KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment> = KeyParser::from_json_str(json, DefaultEnvironment::with_functions(&["function_one", "function_two", "funky"])).unwrap();
+        kp.add_layer("user", "./key_maps");
+        kp.add_layer("missing", "./does_not_exist");
+        assert_eq!(kp.layers().len(), 2);
+        kp.init().unwrap();
+        assert_eq!(kp.parse_key_sequence(&[KeyCode::from("z")]), Ok(vec![&FunctionString::from("function_two")]));
+        // the user layer rebinds a to sub_command_one
+        assert_eq!(kp.parse_key_sequence(&[KeyCode::from("a")]), Ok(vec![&FunctionString::from("function_one"), &FunctionString::from("function_two")]));
+        assert!(KeyParser::<Mode, KeyCode, FunctionString, DefaultEnvironment>::from_json_str("{", DefaultEnvironment::new()).is_err());
+    }
+
+    #[test]
+    fn runtime_binding_test() {
+        let mut kp: KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment> = KeyParser::default();
+        kp.env.set_functions(vec![FunctionString::from("function_one"), FunctionString::from("function_two"), FunctionString::from("funky")]);
+        assert_eq!(kp.add_binding(KeyMap::new(&["x"], "function_two")), Err(KeyMapError::NotInitialized));
+        kp.init().unwrap();
+
+        kp.add_binding(KeyMap::new(&["x", "y"], "command_one")).unwrap();
+        assert!(kp.add_binding(KeyMap::new(&["z"], "missing")).is_err());
+        kp.remove_binding(KeyMap::new(&["c"], "")).unwrap();
+        assert_eq!(kp.remove_binding(KeyMap::new(&["c"], "")).unwrap_err().to_string(), "Invalid key combination: [c]");
+        kp.add_binding(KeyMap::new(&["d"], "-sub_command_one")).unwrap();
+        assert!(kp.parse_key_sequence(&[KeyCode::from("d")]).is_err());
+        kp.reload().unwrap();
+        assert!(kp.parse_key_sequence(&[KeyCode::from("d")]).is_err());
+        assert_eq!(kp.parse_key_sequence(&[KeyCode::from("x"), KeyCode::from("y")]), Ok(vec![&FunctionString::from("function_one"), &FunctionString::from("function_two")]));
+        assert!(kp.parse_key_sequence(&[KeyCode::from("c")]).is_err());
+    }
+
+    #[test]
+    fn remove_last_continuation_test() {
+        let json = r#"{"key_maps": [{"keys": ["a"], "command": "function_one"}, {"keys": ["a", "b"], "command": "function_two"}]}"#;
+        let mut kp: KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment> = KeyParser::from_json_str(json, DefaultEnvironment::with_functions(&["function_one", "function_two"])).unwrap();
+        kp.init().unwrap();
+        assert_eq!(kp.key_by_key(KeyCode::from("a")), Ok(None));
+        kp.cancel();
+        kp.remove_binding(KeyMap::new(&["a", "b"], "")).unwrap();
+        // a is no prefix anymore and fires without waiting
+        assert_eq!(kp.key_by_key(KeyCode::from("a")), Ok(Some(vec![&FunctionString::from("function_one")])));
+    }
+
+    #[test]
+    fn key_press_test() {
+        use types::KeyPress;
+        let json = r#"{"key_maps": [{"keys": ["<c-k>", "<Ctrl-c>"], "command": "function_one"}]}"#;
+        let mut kp: KeyParser<Mode, KeyPress, FunctionString, DefaultEnvironment> = KeyParser::from_json_str(json, DefaultEnvironment::with_functions(&["function_one"])).unwrap();
+        kp.init().unwrap();
+        assert_eq!(kp.parse_key_sequence(&[KeyPress::from("<C-k>"), KeyPress::from("<control-c>")]), Ok(vec![&FunctionString::from("function_one")]));
+    }
+
+    #[test]
+    fn export_round_trip_test() {
+        let mut kp: KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment> = KeyParser::default();
+        kp.env.set_functions(vec![FunctionString::from("function_one"), FunctionString::from("function_two"), FunctionString::from("funky")]);
+        assert_eq!(kp.export(), Err(KeyMapError::NotInitialized));
+        kp.init().unwrap();
+        kp.add_binding(KeyMap::new(&["x"], "command_one")).unwrap();
+        kp.remove_binding(KeyMap::new(&["c"], "")).unwrap();
+
+        let root = std::env::temp_dir().join("key_map_export_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("export.json"), kp.export().unwrap()).unwrap();
+        let mut exported: KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment> = KeyParser::new(root.to_str().unwrap().to_owned(), DefaultEnvironment::with_functions(&["function_one", "function_two", "funky"]));
+        let result = exported.init();
+        std::fs::remove_dir_all(&root).unwrap();
+        result.unwrap();
+
+        let sorted = |kp: &KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment>| {
+            let mut infos = kp.commands();
+            infos.iter_mut().flat_map(|info| info.bindings.values_mut()).for_each(|keys| keys.sort_by_key(|k| format!("{k:?}")));
+            infos
+        };
+        assert_eq!(sorted(&exported), sorted(&kp));
+        for keys in [vec!["a"], vec!["a", "b"], vec!["b"], vec!["c"], vec!["d"], vec!["x"]] {
+            let keys: Vec<KeyCode> = keys.into_iter().map(KeyCode::from).collect();
+            assert_eq!(exported.parse_key_sequence(&keys), kp.parse_key_sequence(&keys));
+        }
+        assert!(kp.cheat_sheet(CheatSheetFormat::Markdown).unwrap().contains("## Normal"));
+    }
+
+    #[test]
+    fn malformed_when_test() {
+        let json = r#"{"key_maps": [{"keys": ["a"], "command": "function_one", "when": "a &&"}]}"#;
+        let mut kp: KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment> = KeyParser::from_json_str(json, DefaultEnvironment::with_functions(&["function_one"])).unwrap();
+        assert!(matches!(kp.init(), Err(KeyMapError::WhenExpression { ref expression, .. }) if expression == "a &&"));
+    }
+
+    #[test]
+    fn send_sync_test() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment>>();
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn reload_if_changed_test() {
+        let root = std::env::temp_dir().join("key_map_reload_if_changed_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("map.json");
+        std::fs::write(&file, r#"{"key_maps": [{"keys": ["a"], "command": "function_one"}]}"#).unwrap();
+        let mut kp: KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment> = KeyParser::new(root.to_str().unwrap().to_owned(), DefaultEnvironment::with_functions(&["function_one"]));
+        kp.init().unwrap();
+        let mut watcher = kp.watcher();
+        assert_eq!(kp.reload_if_changed(&mut watcher), Ok(watch::Changes::default()));
+
+        let broken = root.join("broken.json");
+        std::fs::write(&broken, "{").unwrap();
+        let error = kp.reload_if_changed(&mut watcher).unwrap_err();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(error.changes.added, vec![broken]);
+        assert!(matches!(error.error, KeyMapError::JsonParse { .. }));
+        assert_eq!(kp.parse_key_sequence(&[KeyCode::from("a")]), Ok(vec![&FunctionString::from("function_one")]));
+    }
+
+    #[test]
+    fn validate_test() {
+        let mut kp: KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment> = KeyParser::default();
+        kp.env.set_functions(vec![FunctionString::from("function_one"), FunctionString::from("function_two")]);
+        let report = kp.validate();
+        assert!(!report.is_ok());
+        assert!(report.errors().iter().all(|d| d.kind == validation::DiagnosticKind::UnknownFunction));
+        assert!(report.errors().iter().all(|d| d.source.as_deref().is_some_and(|s| s.ends_with("simple.json"))));
+    }
+
+    #[test]
+    fn not_initialized_test() {
+        let kp: KeyParser<Mode, KeyCode, FunctionString, DefaultEnvironment> = KeyParser::default();
+        assert_eq!(kp.parse_key_sequence(&[KeyCode::from("a")]), Err(KeyMapError::NotInitialized));
+    }
 }
@@ -2,6 +2,10 @@ use std::fmt::Display;
 
 use crate::{Function, Key};
 
+mod key_press;
+pub use key_press::{KeyName, KeyPress, Modifiers};
+pub(crate) use key_press::{split_key_sequence, split_vim_keys, vscode_chord_to_vim};
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 /// this is the default type used for modes, since it also acts as a Key it has to implement Key
 pub struct Mode(String);
@@ -0,0 +1,294 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::{Key, KeyMapError};
+
+/// a key with modifiers, parsed from and formatted as vim notation, e.g. "x", "A", "<C-S-x>", "<Esc>" or "<A-F12>".
+/// Equivalent spellings are the same key: "<c-k>", "<C-k>" and "<Ctrl-k>" as well as "A" and "<S-a>".
+/// Letters are stored lower case, upper case letters set shift instead. Like in vim, ctrl with a letter ignores
+/// its case, "<C-W>" is "<C-w>". Shift is only set together with ctrl if it is written, e.g. "<C-S-w>".
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyPress {
+  pub key: KeyName,
+  pub modifiers: Modifiers,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+  pub ctrl: bool,
+  pub alt: bool,
+  pub shift: bool,
+  pub super_key: bool,
+  pub meta: bool,
+}
+
+impl Modifiers {
+  pub fn is_empty(&self) -> bool {
+    *self == Modifiers::default()
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum KeyName {
+  Char(char),
+  Esc,
+  Tab,
+  Enter,
+  Space,
+  Backspace,
+  Delete,
+  Insert,
+  Home,
+  End,
+  PageUp,
+  PageDown,
+  Up,
+  Down,
+  Left,
+  Right,
+  /// F1 to F24
+  F(u8),
+  /// notation which could not be parsed, kept as written. Only created by the infallible From conversions
+  Other(String),
+}
+
+// the vim name is first, it is used for formatting
+const NAMED_KEYS: &[(&[&str], KeyName)] = &[
+  (&["Esc", "Escape"], KeyName::Esc),
+  (&["Tab"], KeyName::Tab),
+  (&["CR", "Enter", "Return"], KeyName::Enter),
+  (&["Space"], KeyName::Space),
+  (&["BS", "Backspace"], KeyName::Backspace),
+  (&["Del", "Delete"], KeyName::Delete),
+  (&["Insert", "Ins"], KeyName::Insert),
+  (&["Home"], KeyName::Home),
+  (&["End"], KeyName::End),
+  (&["PageUp"], KeyName::PageUp),
+  (&["PageDown"], KeyName::PageDown),
+  (&["Up"], KeyName::Up),
+  (&["Down"], KeyName::Down),
+  (&["Left"], KeyName::Left),
+  (&["Right"], KeyName::Right),
+  (&["lt"], KeyName::Char('<')),
+  (&["Bar"], KeyName::Char('|')),
+  (&["Bslash"], KeyName::Char('\\')),
+];
+
+impl KeyPress {
+  pub fn new(key: KeyName, modifiers: Modifiers) -> Self {
+    Self { key, modifiers }.canonical()
+  }
+
+  // letters are lower case with shift, unless ctrl is pressed. A space character is the Space key
+  fn canonical(mut self) -> Self {
+    match self.key {
+      KeyName::Char(c) if c.is_uppercase() && c.to_lowercase().count() == 1 => {
+        self.key = KeyName::Char(c.to_lowercase().next().unwrap_or(c));
+        self.modifiers.shift |= !self.modifiers.ctrl;
+      }
+      KeyName::Char(' ') => self.key = KeyName::Space,
+      _ => {},
+    }
+    self
+  }
+}
+
+impl FromStr for KeyPress {
+  type Err = KeyMapError;
+
+  fn from_str(notation: &str) -> Result<Self, Self::Err> {
+    let error = |message: &str| KeyMapError::InvalidKeyNotation { notation: notation.to_owned(), message: message.to_owned() };
+    let mut chars = notation.chars();
+    match (chars.next(), chars.next()) {
+      (None, _) => return Err(error("empty key")),
+      (Some(c), None) => return Ok(KeyPress::new(KeyName::Char(c), Modifiers::default())),
+      _ => {},
+    }
+    let inner = notation.strip_prefix('<').and_then(|n| n.strip_suffix('>'))
+      .ok_or_else(|| error("a key is a single character or written in angle brackets like <C-x>"))?;
+
+    let mut modifiers = Modifiers::default();
+    let mut rest = inner;
+    // the key itself may be '-', therefore only dashes followed by more text separate modifiers
+    while let Some((modifier, tail)) = rest.split_once('-').filter(|(_, tail)| !tail.is_empty()) {
+      let flag = match modifier.to_lowercase().as_str() {
+        "c" | "ctrl" | "control" => &mut modifiers.ctrl,
+        "a" | "alt" => &mut modifiers.alt,
+        "s" | "shift" => &mut modifiers.shift,
+        "d" | "super" | "cmd" | "win" => &mut modifiers.super_key,
+        "m" | "meta" => &mut modifiers.meta,
+        _ => return Err(error(&format!("unknown modifier {modifier}"))),
+      };
+      *flag = true;
+      rest = tail;
+    }
+
+    let mut chars = rest.chars();
+    let key = match (chars.next(), chars.next()) {
+      (None, _) => return Err(error("missing key")),
+      (Some(c), None) => KeyName::Char(c),
+      _ => parse_key_name(rest).ok_or_else(|| error(&format!("unknown key {rest}")))?,
+    };
+    Ok(KeyPress::new(key, modifiers))
+  }
+}
+
+fn parse_key_name(name: &str) -> Option<KeyName> {
+  if let Some(number) = name.strip_prefix(['F', 'f']).and_then(|n| n.parse::<u8>().ok()) {
+    return (1..=24).contains(&number).then_some(KeyName::F(number));
+  }
+  NAMED_KEYS.iter()
+    .find(|(names, _)| names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+    .map(|(_, key)| key.clone())
+}
+
+impl Display for KeyPress {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let m = self.modifiers;
+    let name = match (&self.key, NAMED_KEYS.iter().find(|(_, key)| *key == self.key)) {
+      (KeyName::Other(raw), _) => return write!(f, "{raw}"),
+      (_, Some((names, _))) => names[0].to_owned(),
+      (KeyName::F(number), None) => format!("F{number}"),
+      (KeyName::Char(c), None) if m.is_empty() => return write!(f, "{c}"),
+      // a shifted letter is written upper case, e.g. "X"
+      (KeyName::Char(c), None) if m == (Modifiers { shift: true, ..Modifiers::default() }) && c.is_lowercase() => {
+        return write!(f, "{}", c.to_uppercase());
+      }
+      (key, None) => match key {
+        KeyName::Char(c) => c.to_string(),
+        key => format!("{key:?}"),
+      },
+    };
+    let prefixes = [(m.ctrl, "C-"), (m.alt, "A-"), (m.meta, "M-"), (m.super_key, "D-"), (m.shift, "S-")];
+    let prefix: String = prefixes.iter().filter(|(set, _)| *set).map(|(_, p)| *p).collect();
+    write!(f, "<{prefix}{name}>")
+  }
+}
+
+impl Key for KeyPress {}
+/// parses vim notation, invalid notation becomes KeyName::Other. Use parse to get an error instead
+impl From<&str> for KeyPress {
+  fn from(notation: &str) -> Self {
+    notation.parse().unwrap_or_else(|_| KeyPress { key: KeyName::Other(notation.to_owned()), modifiers: Modifiers::default() })
+  }
+}
+impl From<String> for KeyPress {
+  fn from(notation: String) -> Self {
+    KeyPress::from(notation.as_str())
+  }
+}
+
+/// splits a key sequence written as one string into single keys. Accepts vim notation like "<C-k><C-c>" or "gg"
+/// and VS Code chords like "ctrl+k ctrl+c". Both are converted to canonical vim notation, e.g. "<c-k>" to "<C-k>".
+/// VS Code chords are recognized by a modifier followed by '+', otherwise whitespace only separates keys, "<C-k> <C-c>"
+/// is the same as "<C-k><C-c>". The space key is written "<Space>"
+pub(crate) fn split_key_sequence(notation: &str) -> Result<Vec<String>, KeyMapError> {
+  let is_chord = notation.split_whitespace().any(|chord| {
+    chord.split_once('+').is_some_and(|(modifier, _)| VSCODE_MODIFIERS.iter().any(|(name, _)| name.eq_ignore_ascii_case(modifier)))
+  });
+  let keys: Vec<String> = if is_chord {
+    notation.split_whitespace().map(vscode_chord_to_vim).collect::<Result<_, _>>()?
+  } else {
+    notation.split_whitespace().map(split_vim_keys).collect::<Result<Vec<_>, _>>()?.concat()
+  };
+  if keys.is_empty() {
+    return Err(KeyMapError::InvalidKeyNotation { notation: notation.to_owned(), message: "empty key sequence".to_owned() });
+  }
+  Ok(keys)
+}
+
+/// splits vim notation like the {lhs} of a mapping into single keys in canonical vim notation. Every character
+/// is a key, including a space. A '<' followed by a letter starts a bracketed key like "<Esc>", any other '<' is the key itself
+pub(crate) fn split_vim_keys(notation: &str) -> Result<Vec<String>, KeyMapError> {
+  let error = |message: &str| KeyMapError::InvalidKeyNotation { notation: notation.to_owned(), message: message.to_owned() };
+  let mut keys = Vec::new();
+  let mut rest = notation;
+  while let Some(c) = rest.chars().next() {
+    let length = if c == '<' && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+      // a bracketed key ends at the first '>', "<C-<>" is ctrl and <
+      rest[1..].find('>').ok_or_else(|| error("unclosed <"))? + 2
+    } else {
+      c.len_utf8()
+    };
+    keys.push(rest[..length].parse::<KeyPress>()?.to_string());
+    rest = &rest[length..];
+  }
+  Ok(keys)
+}
+
+const VSCODE_MODIFIERS: &[(&str, &str)] = &[("ctrl", "C-"), ("alt", "A-"), ("shift", "S-"), ("meta", "M-"), ("cmd", "D-"), ("win", "D-"), ("super", "D-")];
+
+// e.g. "ctrl+shift+k" to "<C-S-k>" and "ctrl++" to "<C-+>"
+pub(crate) fn vscode_chord_to_vim(chord: &str) -> Result<String, KeyMapError> {
+  let error = |message: String| KeyMapError::InvalidKeyNotation { notation: chord.to_owned(), message };
+  let mut prefix = String::new();
+  let mut rest = chord;
+  while let Some((modifier, tail)) = rest.split_once('+').filter(|(modifier, tail)| !modifier.is_empty() && !tail.is_empty()) {
+    let (_, vim) = VSCODE_MODIFIERS.iter().find(|(name, _)| name.eq_ignore_ascii_case(modifier))
+      .ok_or_else(|| error(format!("unknown modifier {modifier}")))?;
+    prefix.push_str(vim);
+    rest = tail;
+  }
+  let key = match rest {
+    "<" => "lt",
+    "|" => "Bar",
+    "\\" => "Bslash",
+    rest => rest,
+  };
+  let key: KeyPress = format!("<{prefix}{key}>").parse().map_err(|_| error(format!("unknown key {rest}")))?;
+  Ok(key.to_string())
+}
+
+#[test]
+fn key_press_parse_test() {
+  let ctrl_k = KeyPress::new(KeyName::Char('k'), Modifiers { ctrl: true, ..Modifiers::default() });
+  assert_eq!("<c-k>".parse::<KeyPress>(), Ok(ctrl_k.clone()));
+  assert_eq!("<C-k>".parse::<KeyPress>(), Ok(ctrl_k.clone()));
+  assert_eq!("<Ctrl-k>".parse::<KeyPress>(), Ok(ctrl_k.clone()));
+  assert_eq!(KeyPress::from("A"), KeyPress::from("<S-a>"));
+  assert_eq!(KeyPress::from("<C-S-x>"), KeyPress::from("<s-c-X>"));
+  assert_eq!(KeyPress::from("<C-W>"), KeyPress::from("<C-w>"));
+  assert_ne!(KeyPress::from("<C-S-w>"), KeyPress::from("<C-w>"));
+  assert_eq!(KeyPress::from("<A-W>"), KeyPress::from("<A-S-w>"));
+  assert_eq!(KeyPress::from("<C-->").key, KeyName::Char('-'));
+  assert_eq!(KeyPress::from("<escape>"), KeyPress::new(KeyName::Esc, Modifiers::default()));
+  assert_eq!(KeyPress::from("<S-F12>").key, KeyName::F(12));
+  assert_eq!(KeyPress::from(" "), KeyPress::from("<Space>"));
+
+  assert!(matches!("<F25>".parse::<KeyPress>(), Err(KeyMapError::InvalidKeyNotation { .. })));
+  assert!(matches!("<X-a>".parse::<KeyPress>(), Err(KeyMapError::InvalidKeyNotation { .. })));
+  assert!(matches!("gg".parse::<KeyPress>(), Err(KeyMapError::InvalidKeyNotation { .. })));
+  assert_eq!(KeyPress::from("<C-"), KeyPress { key: KeyName::Other("<C-".to_owned()), modifiers: Modifiers::default() });
+}
+
+#[test]
+fn key_press_format_test() {
+  let formatted: Vec<String> = ["x", "A", "<s-a>", "<ctrl-k>", "<C-S-x>", "<shift-ctrl-alt-meta-super-Tab>", "<lt>", "<c-<>", "<cr>", "<f1>", " "]
+    .iter().map(|k| KeyPress::from(*k).to_string()).collect();
+  assert_eq!(formatted, vec!["x", "A", "A", "<C-k>", "<C-S-x>", "<C-A-M-D-S-Tab>", "<lt>", "<C-lt>", "<CR>", "<F1>", "<Space>"]);
+  for notation in formatted {
+    assert_eq!(KeyPress::from(notation.as_str()).to_string(), notation);
+  }
+}
+
+#[test]
+fn split_key_sequence_test() {
+  let split = |notation: &str| split_key_sequence(notation).unwrap();
+  assert_eq!(split("gg"), vec!["g", "g"]);
+  assert_eq!(split("<c-k><C-c>"), vec!["<C-k>", "<C-c>"]);
+  // both notations of a key are the same canonical key
+  assert_eq!(split("<c-k><c-c>"), split("ctrl+k ctrl+c"));
+  assert_eq!(split("<C-k> <C-c>"), vec!["<C-k>", "<C-c>"]);
+  assert_eq!(split("<"), vec!["<lt>"]);
+  assert_eq!(split("<<"), vec!["<lt>", "<lt>"]);
+  assert_eq!(split("a<Space>"), vec!["a", "<Space>"]);
+  assert_eq!(split_vim_keys("a b").unwrap(), vec!["a", "<Space>", "b"]);
+  assert_eq!(split("d<Esc>x"), vec!["d", "<Esc>", "x"]);
+  assert_eq!(split("ctrl+k ctrl+shift+c"), vec!["<C-k>", "<C-S-c>"]);
+  assert_eq!(split("ctrl+k escape"), vec!["<C-k>", "<Esc>"]);
+  assert_eq!(split("g+"), vec!["g", "+"]);
+  assert!(matches!(split_key_sequence("<C-k"), Err(KeyMapError::InvalidKeyNotation { .. })));
+  assert!(matches!(split_key_sequence("<Foo>"), Err(KeyMapError::InvalidKeyNotation { .. })));
+  assert!(matches!(split_key_sequence("ctrl+foo+k"), Err(KeyMapError::InvalidKeyNotation { .. })));
+  assert!(matches!(split_key_sequence(""), Err(KeyMapError::InvalidKeyNotation { .. })));
+}
@@ -0,0 +1,318 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use crate::environment::{EnvFunctions, EnvVariables};
+use crate::evaluation_tree::from_key_map_data::{key_map_to_binding, raw_command_to_command};
+use crate::evaluation_tree::command_execution::find_cycles;
+use crate::evaluation_tree::when_expression::{Condition, WhenFallback};
+use crate::json_parser::{Command, CommandType, KeyMap, KeyMapData};
+use crate::{Function, KeyMapError};
+
+// Validation inspects the raw key map data and reports every problem at once.
+// Unlike `init` it never stops at the first error, which makes it suitable for config linting.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  Error,
+  Warning,
+  Info,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+  /// a json file could not be loaded
+  LoadError,
+  /// a key map or command group references a command which does not exist
+  UnknownCommand,
+  /// a command references a function which is not part of the environment
+  UnknownFunction,
+  /// two commands share the same (prefixed) name
+  DuplicateCommand,
+  /// a command has no commands
+  EmptyCommand,
+  /// the same key sequence is bound to different commands in the same mode
+  ConflictingBinding,
+  /// commands include each other in a cycle
+  CommandCycle,
+  /// a command is neither bound to keys nor used by another command
+  UnreachableCommand,
+  /// a when expression is malformed (Error) or can not be evaluated in the current environment (Warning),
+  /// e.g. because its variables are only set at runtime
+  InvalidWhenExpression,
+  /// a function of the environment is never used by any command
+  UnusedFunction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub kind: DiagnosticKind,
+  pub message: String,
+  /// the json file the problem originates from, if it can be attributed to one
+  pub source: Option<String>,
+}
+
+impl Diagnostic {
+  fn new(severity: Severity, kind: DiagnosticKind, message: String, source: &str) -> Self {
+    let source = if source.is_empty() { None } else { Some(source.to_owned()) };
+    Self { severity, kind, message, source }
+  }
+}
+
+impl Display for Diagnostic {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let severity = match self.severity {
+      Severity::Error => "error",
+      Severity::Warning => "warning",
+      Severity::Info => "info",
+    };
+    match self.source {
+      Some(ref source) => write!(f, "{severity}[{source}]: {}", self.message),
+      None => write!(f, "{severity}: {}", self.message),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+  pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ValidationReport {
+  /// true if there are no diagnostics with severity Error
+  pub fn is_ok(&self) -> bool {
+    !self.diagnostics.iter().any(|d| d.severity == Severity::Error)
+  }
+  pub fn errors(&self) -> Vec<&Diagnostic> {
+    self.with_severity(Severity::Error)
+  }
+  pub fn warnings(&self) -> Vec<&Diagnostic> {
+    self.with_severity(Severity::Warning)
+  }
+  pub fn with_severity(&self, severity: Severity) -> Vec<&Diagnostic> {
+    self.diagnostics.iter().filter(|d| d.severity == severity).collect()
+  }
+  pub fn of_kind(&self, kind: DiagnosticKind) -> Vec<&Diagnostic> {
+    self.diagnostics.iter().filter(|d| d.kind == kind).collect()
+  }
+
+  pub(crate) fn from_load_error(error: KeyMapError) -> Self {
+    let errors = match error {
+      KeyMapError::Multiple(errors) => errors,
+      error => vec![error],
+    };
+    let diagnostics = errors.into_iter().map(|error| {
+      let source = match error {
+        KeyMapError::JsonParse { ref path, .. } | KeyMapError::Parse { ref path, .. } | KeyMapError::Io { ref path, .. } => path.clone(),
+        KeyMapError::InvalidPath(ref path) => path.clone(),
+        _ => String::new(),
+      };
+      Diagnostic::new(Severity::Error, DiagnosticKind::LoadError, error.to_string(), &source)
+    }).collect();
+    Self { diagnostics }
+  }
+}
+
+impl Display for ValidationReport {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for diagnostic in &self.diagnostics {
+      writeln!(f, "{diagnostic}")?;
+    }
+    Ok(())
+  }
+}
+
+pub(crate) fn validate<F: Function, E: EnvFunctions<F> + EnvVariables>(data: &KeyMapData, environment: &E) -> ValidationReport {
+  let mut diagnostics = Vec::new();
+  let command_names: Vec<String> = data.commands.iter().map(|c| c.name.clone()).collect();
+
+  // duplicates, unknown functions and commands, when expressions
+  let mut seen: HashSet<&str> = HashSet::new();
+  for raw_command in &data.commands {
+    if !seen.insert(&raw_command.name) {
+      diagnostics.push(Diagnostic::new(Severity::Error, DiagnosticKind::DuplicateCommand,
+        format!("duplicate command name: {}", raw_command.name), &raw_command.source));
+    }
+    let (command, errors) = raw_command_to_command::<F, E>(raw_command, &command_names, environment);
+    let mut when_is_invalid = false;
+    for error in errors {
+      let kind = match error {
+        KeyMapError::FunctionNotFound(_) => DiagnosticKind::UnknownFunction,
+        KeyMapError::WhenExpression { .. } => { when_is_invalid = true; DiagnosticKind::InvalidWhenExpression },
+        KeyMapError::EmptyCommand(_) => DiagnosticKind::EmptyCommand,
+        _ => DiagnosticKind::UnknownCommand,
+      };
+      diagnostics.push(Diagnostic::new(Severity::Error, kind,
+        format!("{error} (in command {})", raw_command.name), &raw_command.source));
+    }
+    if !when_is_invalid {
+      if let Err(error) = command.condition().is_satisfied(environment, WhenFallback::Error) {
+        diagnostics.push(Diagnostic::new(Severity::Warning, DiagnosticKind::InvalidWhenExpression,
+          format!("{error} (in command {})", raw_command.name), &raw_command.source));
+      }
+    }
+  }
+
+  // key maps referencing unknown commands and conflicting bindings.
+  // Bindings conflict if their when expressions parse to the same condition, e.g. "a && b" and "b && a"
+  let mut bindings: Vec<(&str, &[String], Condition, &KeyMap)> = Vec::new();
+  for key_map in &data.key_maps {
+    let condition = match Condition::new(&key_map.when) {
+      Ok(condition) => {
+        if let Err(error) = condition.is_satisfied(environment, WhenFallback::Error) {
+          diagnostics.push(Diagnostic::new(Severity::Warning, DiagnosticKind::InvalidWhenExpression,
+            format!("{error} (in key map {:?})", key_map.keys), &key_map.source));
+        }
+        Some(condition)
+      }
+      Err(error) => {
+        diagnostics.push(Diagnostic::new(Severity::Error, DiagnosticKind::InvalidWhenExpression,
+          format!("{error} (in key map {:?})", key_map.keys), &key_map.source));
+        None
+      }
+    };
+    if let Err(error) = key_map_to_binding::<F, E>(key_map, &command_names, environment) {
+      let kind = match error {
+        KeyMapError::FunctionNotFound(_) => DiagnosticKind::UnknownFunction,
+        _ => DiagnosticKind::UnknownCommand,
+      };
+      diagnostics.push(Diagnostic::new(Severity::Error, kind,
+        format!("{error} (bound to keys {:?})", key_map.keys), &key_map.source));
+    }
+    let Some(condition) = condition else { continue };
+    for mode in &key_map.mode {
+      let same = bindings.iter().position(|(m, keys, c, _)| m == mode && *keys == key_map.keys.as_slice() && *c == condition);
+      let other = same.map(|index| bindings.remove(index).3);
+      bindings.push((mode, &key_map.keys, condition.clone(), key_map));
+      if let Some(other) = other {
+        if other.command != key_map.command {
+          // rebinding keys of an earlier layer is intended, within a layer it is likely a mistake
+          let severity = if other.layer == key_map.layer { Severity::Warning } else { Severity::Info };
+          diagnostics.push(Diagnostic::new(severity, DiagnosticKind::ConflictingBinding,
+            format!("keys {:?} in mode {mode} with when expression \"{}\" are bound to {} (layer {}) and {} (layer {}), the latter wins",
+              key_map.keys, key_map.when, other.command, other.layer, key_map.command, key_map.layer), &key_map.source));
+        }
+      }
+    }
+  }
+
+  // command cycles
+  let graph: HashMap<&str, Vec<&str>> = data.commands.iter().map(|c| (c.name.as_str(), referenced_commands(c, &command_names))).collect();
+  for cycle in find_cycles(&graph) {
+    let source = data.commands.iter().find(|c| c.name == cycle[0]).map(|c| c.source.as_str()).unwrap_or("");
+    diagnostics.push(Diagnostic::new(Severity::Error, DiagnosticKind::CommandCycle,
+      KeyMapError::CommandCycle(cycle).to_string(), source));
+  }
+
+  // unreachable commands
+  let mut used: HashSet<&str> = data.key_maps.iter().map(|k| k.command.as_str()).collect();
+  for raw_command in &data.commands {
+    used.extend(referenced_commands(raw_command, &command_names));
+  }
+  for raw_command in &data.commands {
+    if !used.contains(raw_command.name.as_str()) {
+      diagnostics.push(Diagnostic::new(Severity::Warning, DiagnosticKind::UnreachableCommand,
+        format!("command {} is neither bound to keys nor used by another command", raw_command.name), &raw_command.source));
+    }
+  }
+
+  // unused functions
+  let referenced: HashSet<&str> = data.commands.iter().flat_map(|c| c.commands.iter().map(|f| f.as_str()))
+    .chain(data.key_maps.iter().map(|k| k.command.as_str()))
+    .collect();
+  for function in environment.get_functions() {
+    let name = function.to_string();
+    if !referenced.contains(name.as_str()) {
+      diagnostics.push(Diagnostic::new(Severity::Info, DiagnosticKind::UnusedFunction,
+        format!("function {name} is not used by any command"), ""));
+    }
+  }
+
+  diagnostics.sort_by_key(|d| d.severity);
+  ValidationReport { diagnostics }
+}
+
+fn referenced_commands<'a>(raw_command: &'a Command, command_names: &[String]) -> Vec<&'a str> {
+  if matches!(raw_command.command_type, CommandType::FunctionSequence) {
+    Vec::new()
+  } else {
+    raw_command.commands.iter().filter(|c| command_names.contains(c)).map(|c| c.as_str()).collect()
+  }
+}
+
+//--------------------------------------
+
+#[cfg(test)]
+use crate::{environment::DefaultEnvironment, types::FunctionString};
+
+#[cfg(test)]
+fn test_data() -> KeyMapData {
+  serde_json::from_str(r#"{
+    "commands": [
+      {"name": "one", "commands": ["function_one", "missing_function"], "command_type": "FunctionSequence"},
+      {"name": "one", "commands": ["function_one"]},
+      {"name": "group", "commands": ["one", "missing_command"], "command_type": "CommandGroup"},
+      {"name": "lonely", "commands": ["function_one"], "when": "(("},
+      {"name": "ping", "commands": ["pong"]},
+      {"name": "pong", "commands": ["ping"]}
+    ],
+    "key_maps": [
+      {"keys": ["a"], "command": "group"},
+      {"keys": ["a"], "command": "one"},
+      {"keys": ["b"], "command": "nowhere"},
+      {"keys": ["b"], "command": "one", "when": "false"},
+      {"keys": ["c"], "command": "one", "when": ")"},
+      {"keys": ["p"], "command": "ping"}
+    ]
+  }"#).unwrap()
+}
+
+#[test]
+fn validate_reports_all_problems_test() {
+  let env = DefaultEnvironment::with_functions(&["function_one", "unused"]);
+  let report = validate::<FunctionString, _>(&test_data(), &env);
+  assert!(!report.is_ok());
+  assert_eq!(report.to_string().lines().count(), report.diagnostics.len());
+  assert!(report.to_string().contains("command cycle: ping -> pong -> ping"));
+  assert_eq!(report.of_kind(DiagnosticKind::DuplicateCommand).len(), 1);
+  assert_eq!(report.of_kind(DiagnosticKind::UnknownFunction).len(), 1);
+  assert_eq!(report.of_kind(DiagnosticKind::UnknownCommand).len(), 2);
+  assert_eq!(report.of_kind(DiagnosticKind::ConflictingBinding).len(), 1);
+  assert_eq!(report.of_kind(DiagnosticKind::UnreachableCommand).len(), 1);
+  assert_eq!(report.of_kind(DiagnosticKind::InvalidWhenExpression).len(), 2);
+  assert_eq!(report.of_kind(DiagnosticKind::UnusedFunction).len(), 1);
+  assert_eq!(report.of_kind(DiagnosticKind::CommandCycle)[0].message, "command cycle: ping -> pong -> ping");
+  assert_eq!(report.diagnostics.first().map(|d| d.severity), Some(Severity::Error));
+}
+
+#[test]
+fn validate_when_expressions_test() {
+  let env = DefaultEnvironment::with_functions(&["function_one", "function_two"]);
+  let data: KeyMapData = serde_json::from_str(r#"{
+    "commands": [
+      {"name": "one", "commands": ["function_one"], "when": "set_at_runtime"},
+      {"name": "two", "commands": ["function_two"]}
+    ],
+    "key_maps": [
+      {"keys": ["a"], "command": "one", "when": "x && y"},
+      {"keys": ["a"], "command": "two", "when": "y&&x"}
+    ]
+  }"#).unwrap();
+  let report = validate::<FunctionString, _>(&data, &env);
+  // variables which are only set at runtime can not be evaluated, but the config is valid
+  assert!(report.is_ok(), "{report}");
+  assert_eq!(report.of_kind(DiagnosticKind::InvalidWhenExpression).len(), 3);
+  assert!(report.of_kind(DiagnosticKind::InvalidWhenExpression).iter().all(|d| d.severity == Severity::Warning));
+  assert_eq!(report.of_kind(DiagnosticKind::ConflictingBinding).len(), 1);
+}
+
+#[test]
+fn validate_key_maps_folder_test() {
+  let env = DefaultEnvironment::with_functions(&["function_one", "function_two", "funky"]);
+  let data = crate::json_parser::key_map_data_from_path(std::path::Path::new("./key_maps")).unwrap();
+  let report = validate::<FunctionString, _>(&data, &env);
+  assert!(report.is_ok(), "{report}");
+  let unreachable = report.of_kind(DiagnosticKind::UnreachableCommand);
+  assert_eq!(unreachable.len(), 2);
+  assert!(unreachable.iter().all(|d| d.source.as_deref() == Some("./key_maps/simple.json")));
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::json_parser::{is_key_map_file, Layer};
+use crate::KeyMapError;
+
+/// polls the folders of all layers for added, modified and removed key map files.
+/// No background thread is used, call poll regularly, e.g. from the event loop.
+#[derive(Debug, Default)]
+pub struct FileWatcher {
+  roots: Vec<PathBuf>,
+  snapshot: HashMap<PathBuf, Option<SystemTime>>,
+}
+
+/// the files which changed since the last poll
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Changes {
+  pub added: Vec<PathBuf>,
+  pub modified: Vec<PathBuf>,
+  pub removed: Vec<PathBuf>,
+}
+
+impl Changes {
+  pub fn is_empty(&self) -> bool {
+    self.added.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+  }
+}
+
+/// a reload after changes failed, the previous evaluation tree is still in use
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReloadError {
+  /// the changes which triggered the reload
+  pub changes: Changes,
+  pub error: KeyMapError,
+}
+
+impl std::fmt::Display for ReloadError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let changed: Vec<String> = self.changes.added.iter().chain(&self.changes.modified).chain(&self.changes.removed)
+      .map(|path| path.display().to_string())
+      .collect();
+    write!(f, "reload after changes of [{}] failed: {}", changed.join(", "), self.error)
+  }
+}
+
+impl std::error::Error for ReloadError {}
+
+impl FileWatcher {
+  pub fn new(layers: &[Layer]) -> Self {
+    let roots = layers.iter().map(|layer| PathBuf::from(&layer.path)).collect();
+    let mut watcher = Self { roots, snapshot: HashMap::new() };
+    watcher.snapshot = watcher.take_snapshot();
+    watcher
+  }
+
+  pub fn poll(&mut self) -> Changes {
+    let snapshot = self.take_snapshot();
+    let mut changes = Changes::default();
+    for (path, modified) in &snapshot {
+      match self.snapshot.get(path) {
+        None => changes.added.push(path.clone()),
+        Some(old) if old != modified => changes.modified.push(path.clone()),
+        Some(_) => {},
+      }
+    }
+    changes.removed = self.snapshot.keys().filter(|path| !snapshot.contains_key(*path)).cloned().collect();
+    changes.added.sort();
+    changes.modified.sort();
+    changes.removed.sort();
+    self.snapshot = snapshot;
+    changes
+  }
+
+  fn take_snapshot(&self) -> HashMap<PathBuf, Option<SystemTime>> {
+    let mut snapshot = HashMap::new();
+    for root in &self.roots {
+      collect_key_map_files(root, &mut snapshot);
+    }
+    snapshot
+  }
+}
+
+// unreadable directories are skipped, loading reports them once the files are reloaded
+fn collect_key_map_files(dir: &Path, snapshot: &mut HashMap<PathBuf, Option<SystemTime>>) {
+  let entries = match fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(_) => return,
+  };
+  for entry in entries.flatten() {
+    let path = entry.path();
+    if path.is_dir() {
+      collect_key_map_files(&path, snapshot);
+    } else if is_key_map_file(&path) {
+      let modified = entry.metadata().and_then(|m| m.modified()).ok();
+      snapshot.insert(path, modified);
+    }
+  }
+}
+
+#[test]
+fn file_watcher_test() {
+  use std::time::Duration;
+  let root = std::env::temp_dir().join("key_map_watch_test");
+  let _ = fs::remove_dir_all(&root);
+  fs::create_dir_all(root.join("sub")).unwrap();
+  let first = root.join("first.json");
+  fs::write(&first, "{}").unwrap();
+
+  let mut watcher = FileWatcher::new(&[Layer::new("default", root.to_str().unwrap())]);
+  assert!(watcher.poll().is_empty());
+
+  let second = root.join("sub").join("second.json");
+  fs::write(&second, "{}").unwrap();
+  fs::write(root.join("ignored.txt"), "").unwrap();
+  let file = fs::OpenOptions::new().write(true).open(&first).unwrap();
+  file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+  let changes = watcher.poll();
+  assert_eq!(changes.added, vec![second]);
+  assert_eq!(changes.modified, vec![first.clone()]);
+
+  fs::remove_file(&first).unwrap();
+  let changes = watcher.poll();
+  fs::remove_dir_all(&root).unwrap();
+  assert_eq!(changes, Changes { removed: vec![first], ..Changes::default() });
+}